@@ -0,0 +1,745 @@
+// The passes that turn a source file into a Program: first every label gets an address,
+// then the instructions and the data are encoded.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::error::{self, AssembleError, ErrorKind, MacroCall};
+use crate::expr::{self, Expr};
+use crate::instruction::Instruction;
+use crate::isa::{self, Extend, Format, InstrSpec, Slot};
+use crate::lexer::{Span, Token, TokenKind};
+use crate::preprocess::{preprocess, Expansion, Output, SourceFile};
+use crate::pseudo::{self, Pseudo};
+use crate::symbols::{Constant, Symbols};
+use crate::{AddressModel, Addressing, Endian, Options, Program, Symbol, SymbolKind};
+
+// Data labels are reached from $gp, when the address model has one
+const GP : u32 = 28;
+
+#[derive(Copy,Clone,PartialEq)]
+enum Section { Text, Data, KText, KData }
+
+// An instruction or data directive that made it through the label pass, for the next ones to encode
+struct Stmt<'a> {
+    section : Section,
+    tokens : &'a [Token],
+    scope : usize, // Constant definitions before it, to know which .set it sees
+    offset : u32, // Where its data starts, in bytes
+    pseudo : Option<(&'static Pseudo, &'static pseudo::Expansion)>, // The expansion picked for a pseudo-instruction
+}
+
+// What a single comma separated operand turned out to be
+#[derive(Debug,Clone,PartialEq)]
+enum Operand<'a> {
+    Reg(&'a Token),
+    Expr(Expr),
+    // [offset](base), where base is either a register or a data label
+    Offset(Option<Expr>, &'a Token),
+}
+
+/// Assembles a whole source file. On failure every error found is returned, in file order.
+/// Files it includes are looked for in the current directory, then in opts.include_paths.
+pub fn assemble(fdata : &str, opts : &Options) -> Result<Program, Vec<AssembleError>> {
+    assemble_source(fdata, None, opts)
+}
+
+/// Reads and assembles the file at path, looking for the files it includes next to it first.
+pub fn assemble_file(path : &Path, opts : &Options) -> Result<Program, Vec<AssembleError>> {
+    match fs::read_to_string(path) {
+        Ok(fdata) => assemble_source(&fdata, Some(path), opts),
+        Err(e) => Err(vec![AssembleError::global(ErrorKind::CantRead(path.display().to_string(), e.to_string()))]),
+    }
+}
+
+fn assemble_source(text : &str, path : Option<&Path>, opts : &Options) -> Result<Program, Vec<AssembleError>> {
+    // Every problem found is collected here, and there is no Program unless this stays empty
+    let mut errors : Vec<AssembleError> = Vec::new();
+    // Included files go after the text, so every span points into fdata whichever file it came from
+    let Output { lines, expansions, source, files } = preprocess(text, path, opts, &mut errors);
+    let fdata : &str = &source;
+    let err = |kind : ErrorKind, span : Span| AssembleError::new(kind, span, fdata);
+
+    // .text means the program instructions/code, .data means program data, and files start out in .text
+    // .ktext and .kdata are the same for the exception handler, which is kept apart from the program
+    // Each section can be opened any number of times, picking up where it was left
+    let mut section : Section = Section::Text;
+    let mut word_count : u32 = 0; // Used for jump labels
+    let mut kernel_words : u32 = 0; // Same for .ktext, which starts at the exception vector
+    let mut mem_byte_alignment : u32 = 0; // Used for data labels
+    let mut kernel_bytes : u32 = 0; // Same for .kdata, which goes after all the .data
+    let mut kernel_align : u32 = 8; // The largest alignment in the .kdata, which it keeps once moved there
+    // Both have to fit in the data memory, and the data after the first line that doesn't is left out
+    let data_limit : u32 = opts.depth.saturating_mul(4);
+    let mut too_much_data : bool = false;
+    let mut kernel_labels : Vec<String> = Vec::new();
+    let mut label_lines : HashMap<String, u32> = HashMap::new(); // Where each label is defined, for errors
+    let mut symbols : Symbols = Symbols::default();
+    symbols.model = opts.address_model;
+    for (name, value) in &opts.defines {
+        symbols.constants.push(Constant { name: name.clone(), value: Expr::Num(*value), span: None, set: false });
+    }
+    let mut stmts : Vec<Stmt> = Vec::new();
+    // Data labels wait for the next line, so they point past the padding that aligns its data
+    let mut pending : Vec<String> = Vec::new();
+    // Get all Jump and Data labels, and the constant definitions
+    for line in &lines {
+        let (labels, body) = split_labels(line);
+        for tok in labels {
+            let TokenKind::Identifier(label) = &tok.kind else { unreachable!() };
+            if symbols.is_label(label) || pending.contains(label) {
+                errors.push(err(ErrorKind::DuplicateLabel(label.clone()), tok.span));
+                continue;
+            }
+            match section {
+                Section::Text => { symbols.jump_labels.insert(label.clone(), word_count); }
+                Section::KText => { symbols.jump_labels.insert(label.clone(), opts.exception_vector.saturating_add(kernel_words)); }
+                Section::Data | Section::KData => pending.push(label.clone()),
+            }
+            if matches!(section, Section::KText | Section::KData) { kernel_labels.push(label.clone()); }
+            label_lines.insert(label.clone(), tok.span.line);
+        }
+        let Some(head) = body.first() else { continue }; // Only labels on this line
+        let scope : usize = symbols.constants.len();
+        let layout = match (&head.kind, section) {
+            (TokenKind::Directive(d), Section::Data | Section::KData) if is_data_directive(d) => Some(data_layout(d, body, &symbols, scope, fdata)),
+            _ => None,
+        };
+        let offset : &mut u32 = if section == Section::KData { &mut kernel_bytes } else { &mut mem_byte_alignment };
+        if let Some(Ok((align, _))) = layout {
+            *offset = offset.checked_next_multiple_of(align).filter(|&aligned| aligned <= data_limit).unwrap_or(data_limit);
+            if section == Section::KData { kernel_align = kernel_align.max(align); }
+        }
+        for label in pending.drain(..) {
+            symbols.data_labels.insert(label, *offset);
+        }
+        match (&head.kind, section) {
+            (TokenKind::Directive(d), _) if d == "text" => section = Section::Text,
+            (TokenKind::Directive(d), _) if d == "data" => section = Section::Data,
+            (TokenKind::Directive(d), _) if d == "ktext" => section = Section::KText,
+            (TokenKind::Directive(d), _) if d == "kdata" => section = Section::KData,
+            // Everything is visible to everything else in a single file, so there's nothing to do
+            (TokenKind::Directive(d), _) if d == "globl" => (),
+            (TokenKind::Directive(d), _) if d == "eqv" || d == "equ" || d == "set" => match define_constant(body, &symbols, fdata) {
+                Ok(constant) => symbols.constants.push(constant),
+                Err(e) => errors.push(e),
+            },
+            (TokenKind::Directive(_), _) if layout.is_some() => match layout.unwrap() {
+                Ok((_, size)) => match offset.checked_add(size).filter(|&end| end <= data_limit) {
+                    Some(end) => {
+                        stmts.push(Stmt { section, tokens: body, scope, offset: *offset, pseudo: None });
+                        *offset = end;
+                    }
+                    None if !too_much_data => {
+                        too_much_data = true;
+                        errors.push(err(ErrorKind::TooMuchData(opts.depth), head.span));
+                    }
+                    None => (),
+                },
+                Err(e) => errors.push(e),
+            },
+            (TokenKind::Directive(d), _) if is_data_directive(d) => {
+                errors.push(err(ErrorKind::WrongSection(format!(".{}", d), ".data or .kdata"), head.span));
+            }
+            (TokenKind::Directive(d), _) => errors.push(err(ErrorKind::UnsupportedDirective(format!(".{}", d)), head.span)),
+            (TokenKind::Mnemonic(_), Section::Text | Section::KText) => {
+                // A pseudo-instruction takes as many words as its expansion, so the labels after it are right
+                let pseudo = find_pseudo(body, &symbols, scope, opts, fdata);
+                let size : u32 = pseudo.map_or(1, |(_, expansion)| expansion.lines.len() as u32);
+                if section == Section::Text { word_count += size; } else { kernel_words += size; }
+                stmts.push(Stmt { section, tokens: body, scope, offset: 0, pseudo });
+            }
+            (TokenKind::Mnemonic(m), _) => errors.push(err(ErrorKind::WrongSection(m.clone(), ".text or .ktext"), head.span)),
+            _ => errors.push(err(ErrorKind::ExpectedStatement, head.span)),
+        }
+    }
+    for label in pending {
+        symbols.data_labels.insert(label, if section == Section::KData { kernel_bytes } else { mem_byte_alignment });
+    }
+    // Now that the .data is all there, the .kdata can go after it
+    if kernel_bytes > 0 {
+        let kernel_start : Option<u32> = mem_byte_alignment.checked_next_multiple_of(kernel_align);
+        match kernel_start.and_then(|start| Some((start, start.checked_add(kernel_bytes)?))).filter(|&(_, end)| end <= data_limit) {
+            Some((kernel_start, end)) => {
+                for label in &kernel_labels {
+                    if let Some(address) = symbols.data_labels.get_mut(label) { *address += kernel_start; }
+                }
+                for stmt in stmts.iter_mut().filter(|stmt| stmt.section == Section::KData) {
+                    stmt.offset += kernel_start;
+                }
+                mem_byte_alignment = end;
+            }
+            None => {
+                if !too_much_data { errors.push(AssembleError::global(ErrorKind::TooMuchData(opts.depth))); }
+                stmts.retain(|stmt| stmt.section != Section::KData);
+            }
+        }
+    }
+    // Only the first definition of each constant, so a name that is .set several times is reported once
+    for (i, constant) in symbols.constants.iter().enumerate() {
+        if symbols.is_label(&constant.name) && symbols.constants[..i].iter().all(|c| c.name != constant.name) {
+            let kind = ErrorKind::ConstantIsLabel(constant.name.clone(), label_lines[&constant.name]);
+            errors.push(match constant.span {
+                Some(span) => err(kind, span),
+                None => AssembleError::global(kind),
+            });
+        }
+    }
+    // The processor starts running at word 0, so when the entry point is anywhere else
+    // a jump to it is put there first and all the code moves one word up.
+    // Without an entry point given, it's main if there is one, and otherwise just word 0
+    let entry : Option<&str> = opts.entry.as_deref().or(Some("main").filter(|main| symbols.jump_labels.contains_key(*main)));
+    let reset_stub : bool = match entry.map(|entry| (entry, symbols.jump_labels.get(entry))) {
+        None => false,
+        Some((_, Some(&address))) => address != 0,
+        Some((entry, None)) => {
+            let kind = if symbols.data_labels.contains_key(entry) { ErrorKind::EntryNotCode(String::from(entry)) }
+                else { ErrorKind::MissingEntry(String::from(entry)) };
+            errors.push(AssembleError::global(kind));
+            false
+        }
+    };
+    if reset_stub {
+        for (label, address) in symbols.jump_labels.iter_mut() {
+            if !kernel_labels.contains(label) { *address += 1; }
+        }
+    }
+    // The .ktext code is only laid out when the vector is inside the program memory
+    let vector_fits : bool = opts.exception_vector < opts.depth;
+    if kernel_words > 0 && !vector_fits {
+        errors.push(AssembleError::global(ErrorKind::VectorOutOfMemory(opts.exception_vector, opts.depth)));
+    }
+    else if kernel_words > 0 && word_count + reset_stub as u32 > opts.exception_vector {
+        errors.push(AssembleError::global(ErrorKind::KernelOverlap(opts.exception_vector)));
+    }
+    // Constants can use labels, so they get their values once every label is in place
+    symbols.resolve(fdata, &mut errors);
+
+    let mut includes : Vec<PathBuf> = Vec::new();
+    for file in files.iter().filter(|file| file.included_at.is_some()) {
+        if !includes.contains(&file.path) { includes.push(file.path.clone()); }
+    }
+    let mut program = Program { text: Vec::new(), data: Vec::new(), symbols: HashMap::new(), includes };
+    // The same addresses the code was assembled with
+    for (label, &offset) in &symbols.jump_labels {
+        let address = opts.address_model.text_address(offset) as u32;
+        program.symbols.insert(label.clone(), Symbol { kind: SymbolKind::Jump, address, offset });
+    }
+    for (label, &offset) in &symbols.data_labels {
+        let address = opts.address_model.data_address(offset) as u32;
+        program.symbols.insert(label.clone(), Symbol { kind: SymbolKind::Data, address, offset });
+    }
+
+    // Generate each instruction
+    word_count = 0;
+    if reset_stub {
+        if let Some(profile) = opts.profile.as_ref().filter(|_| !allowed("j", opts)) {
+            errors.push(AssembleError::global(ErrorKind::StubNotInProfile(profile.name.clone())));
+        }
+        let jump = isa::find_instruction(&opts.instructions, "j", 1).unwrap();
+        let model : &AddressModel = &opts.address_model;
+        let target = jump_target(model, model.text_address(symbols.jump_labels[entry.unwrap()]), model.text_address(1));
+        match target.and_then(|target| Instruction::J { opcode: jump.opcode, target }.encode().map_err(ErrorKind::FieldOverflow)) {
+            Ok(word) => program.text.push(word),
+            Err(kind) => errors.push(AssembleError::global(kind)),
+        }
+        word_count += 1;
+    }
+    let kernel = stmts.iter().filter(|stmt| stmt.section == Section::KText && vector_fits);
+    for stmt in stmts.iter().filter(|stmt| stmt.section == Section::Text).chain(kernel) {
+        // The exception handler starts right at the vector, with zeros up to it
+        if stmt.section == Section::KText && word_count < opts.exception_vector {
+            program.text.resize(opts.exception_vector as usize, 0);
+            word_count = opts.exception_vector;
+        }
+        let lines : Vec<Vec<Token>> = match stmt.pseudo {
+            Some((pseudo, expansion)) => match check_pseudo(pseudo, expansion, stmt.tokens, &symbols, stmt.scope, opts, fdata) {
+                Ok(ops) => pseudo::expand(expansion, stmt.tokens[0].span, &ops),
+                Err(e) => {
+                    errors.push(e);
+                    // Still take up the words it was given, for the branches after it
+                    word_count += expansion.lines.len() as u32;
+                    program.text.extend(expansion.lines.iter().map(|_| 0));
+                    continue;
+                }
+            },
+            None => vec![stmt.tokens.to_vec()],
+        };
+        for line in &lines {
+            word_count += 1;
+            match encode_instruction(line, word_count, &symbols, stmt.scope, opts, fdata) {
+                Ok(word) => program.text.push(word),
+                Err(e) => {
+                    errors.push(e);
+                    program.text.push(0);
+                }
+            }
+        }
+    }
+    if word_count > opts.depth {
+        errors.push(AssembleError::global(ErrorKind::TooManyInstructions(opts.depth)));
+    }
+
+    // Data is laid out byte by byte, then packed into words
+    let mut bytes : Vec<u8> = vec![0; mem_byte_alignment as usize];
+    for stmt in stmts.iter().filter(|stmt| matches!(stmt.section, Section::Data | Section::KData)) {
+        let line : &[Token] = stmt.tokens;
+        let TokenKind::Directive(d) = &line[0].kind else { unreachable!() };
+        let size : usize = match d.as_str() {
+            "word" | "float" => 4,
+            "half" => 2,
+            "byte" => 1,
+            "double" => 8,
+            _ => continue, // .space and .align only leave zeros
+        };
+        for (i, op) in operands(&line[1..]).into_iter().enumerate() {
+            let float = |op : &[Token]| float_value(op, &symbols, stmt.scope, line[0].span, fdata);
+            let value : u64 = match d.as_str() {
+                // IEEE-754 single and double precision
+                "float" => float(op).map(|v| (v as f32).to_bits() as u64),
+                "double" => float(op).map(f64::to_bits),
+                _ => integer_value(op, size as u32 * 8, &symbols, stmt.scope, line[0].span, fdata).map(|v| v as u64),
+            }.unwrap_or_else(|e| {
+                errors.push(e);
+                0
+            });
+            let at : usize = stmt.offset as usize + i * size;
+            match opts.endian {
+                Endian::Big => bytes[at..at + size].copy_from_slice(&value.to_be_bytes()[8 - size..]),
+                Endian::Little => bytes[at..at + size].copy_from_slice(&value.to_le_bytes()[..size]),
+            }
+        }
+    }
+    for chunk in bytes.chunks(4) {
+        let mut word : [u8; 4] = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        program.data.push(match opts.endian {
+            Endian::Big => u32::from_be_bytes(word),
+            Endian::Little => u32::from_le_bytes(word),
+        });
+    }
+
+    if !errors.is_empty() {
+        finish_errors(&mut errors, &expansions, &files, fdata);
+        return Err(errors);
+    }
+    Ok(program)
+}
+
+// Names the file of every error, adds the macro calls that led to errors
+// inside macro bodies, and puts them all in the order their lines were read
+fn finish_errors(errors : &mut [AssembleError], expansions : &[Expansion], files : &[SourceFile], src : &str) {
+    let file_of = |span : Span| files.iter().find(|f| (f.start..f.end).contains(&span.start));
+    let file_name = |span : Span| file_of(span).map(|f| f.path.display().to_string());
+    for e in errors.iter_mut() {
+        e.context.file = e.span.and_then(file_name);
+        let mut span = e.span;
+        while let Some(s) = span.filter(|s| s.expansion != 0) {
+            let expansion = &expansions[s.expansion as usize - 1];
+            // A macro calling itself is only shown once, not once per level
+            if e.context.calls.last().is_none_or(|call| call.span.start != expansion.call.start) {
+                e.context.calls.push(MacroCall { name: expansion.name.clone(), span: expansion.call,
+                    snippet: error::snippet(src, expansion.call), file: file_name(expansion.call) });
+            }
+            span = Some(expansion.call);
+        }
+    }
+    // Each pass reports its own errors, so sort them by where they are in the main file,
+    // going through the macro calls and .include lines that brought them in
+    errors.sort_by_cached_key(|e| {
+        let mut key : Vec<(u32, u32)> = Vec::new();
+        let mut span = e.span;
+        while let Some(s) = span {
+            key.push((s.line, s.col));
+            span = if s.expansion != 0 { Some(expansions[s.expansion as usize - 1].call) }
+                else { file_of(s).and_then(|f| f.included_at) };
+        }
+        if key.is_empty() { key.push((u32::MAX, 0)); }
+        key.reverse();
+        key
+    });
+}
+
+
+// Splits off the labels defined at the start of a line, leaving the instruction or directive (if any)
+pub fn split_labels(line : &[Token]) -> (Vec<&Token>, &[Token]) {
+    let mut labels : Vec<&Token> = Vec::new();
+    let mut rest = line;
+    while let [label @ Token { kind: TokenKind::Identifier(_), .. }, Token { kind: TokenKind::Colon, .. }, tail @ ..] = rest {
+        labels.push(label);
+        rest = tail;
+    }
+    (labels, rest)
+}
+
+// .eqv NAME, value (the comma is optional, like in MARS), and the same for .equ and .set
+fn define_constant(body : &[Token], symbols : &Symbols, src : &str) -> Result<Constant, AssembleError> {
+    let TokenKind::Directive(d) = &body[0].kind else { unreachable!() };
+    let [_, name_tok @ Token { kind: TokenKind::Identifier(name), .. }, rest @ ..] = body else {
+        return Err(AssembleError::new(ErrorKind::ExpectedConstantName(format!(".{}", d)), body[0].span, src));
+    };
+    let rest = match rest {
+        [Token { kind: TokenKind::Comma, .. }, tail @ ..] => tail,
+        _ => rest,
+    };
+    if rest.is_empty() {
+        return Err(AssembleError::new(ErrorKind::MissingOperand, name_tok.span, src));
+    }
+    let set : bool = d == "set";
+    // Only names that were always .set can be .set again
+    if let Some(old) = symbols.constants.iter().find(|c| c.name == *name) {
+        let kind = match old.span {
+            Some(span) => ErrorKind::ConstantRedefined(name.clone(), span.line),
+            None => ErrorKind::DefinedOnCommandLine(name.clone()),
+        };
+        if !(set && old.set) { return Err(AssembleError::new(kind, name_tok.span, src)); }
+    }
+    Ok(Constant { name: name.clone(), value: expr::parse(rest, src)?, span: Some(name_tok.span), set })
+}
+
+fn is_data_directive(directive : &str) -> bool {
+    matches!(directive, "word" | "half" | "byte" | "float" | "double" | "space" | "align")
+}
+
+// The alignment and the size in bytes of a data directive. .space without a size reserves
+// a word, and .align n only pads up to the next multiple of 2^n
+fn data_layout(directive : &str, body : &[Token], symbols : &Symbols, scope : usize, src : &str) -> Result<(u32, u32), AssembleError> {
+    let args : Vec<&[Token]> = operands(&body[1..]);
+    let value = |op : &[Token]| symbols.early_value(&expr::parse(op, src)?, scope, src);
+    Ok(match (directive, args.as_slice()) {
+        ("word", _) => (4, 4 * args.len() as u32),
+        ("half", _) => (2, 2 * args.len() as u32),
+        ("byte", _) => (1, args.len() as u32),
+        ("float", _) => (4, 4 * args.len() as u32),
+        ("double", _) => (8, 8 * args.len() as u32),
+        ("space", []) => (1, 4),
+        ("space", [op]) => match value(op)? {
+            n if (0..=u32::MAX as i64).contains(&n) => (1, n as u32),
+            n => return Err(AssembleError::new(ErrorKind::NegativeSpace(n), Span::covering(op), src)),
+        },
+        ("align", [op]) => match value(op)? {
+            n if (0..=3).contains(&n) => (1 << n, 0),
+            n => return Err(AssembleError::new(ErrorKind::BadAlign(n), Span::covering(op), src)),
+        },
+        _ => {
+            let kind = ErrorKind::OperandCount(format!(".{}", directive), 1, args.len());
+            return Err(AssembleError::new(kind, Span::covering(body), src));
+        }
+    })
+}
+
+// A .word, .half or .byte value, which can be written both as a signed and an unsigned number
+fn integer_value(op : &[Token], bits : u32, symbols : &Symbols, scope : usize, at : Span, src : &str) -> Result<u32, AssembleError> {
+    match parse_operand(op, false, at, src)? {
+        Operand::Expr(e) => {
+            let value = e.eval(&mut |name| symbols.value(name, scope), src)?;
+            fit(value, bits, value < 0, Span::covering(op), src)
+        }
+        _ => Err(AssembleError::new(ErrorKind::NotImmediate(String::from(token_text(op, src))), op[0].span, src)),
+    }
+}
+
+// A .float or .double value: a float literal with an optional sign, or else any integer expression
+fn float_value(op : &[Token], symbols : &Symbols, scope : usize, at : Span, src : &str) -> Result<f64, AssembleError> {
+    match op {
+        [Token { kind: TokenKind::Float(v), .. }] => return Ok(*v),
+        [Token { kind: TokenKind::Minus, .. }, Token { kind: TokenKind::Float(v), .. }] => return Ok(-v),
+        [Token { kind: TokenKind::Plus, .. }, Token { kind: TokenKind::Float(v), .. }] => return Ok(*v),
+        _ => (),
+    }
+    match parse_operand(op, false, at, src)? {
+        Operand::Expr(e) => Ok(e.eval(&mut |name| symbols.value(name, scope), src)? as f64),
+        _ => Err(AssembleError::new(ErrorKind::NotImmediate(String::from(token_text(op, src))), op[0].span, src)),
+    }
+}
+
+// The pseudo-instruction a line is, if it is one, and the expansion it takes.
+// When the operand that picks it can't be worked out yet, it takes the longest one
+fn find_pseudo(line : &[Token], symbols : &Symbols, scope : usize, opts : &Options, src : &str) -> Option<(&'static Pseudo, &'static pseudo::Expansion)> {
+    let TokenKind::Mnemonic(m) = &line[0].kind else { unreachable!() };
+    let ops : Vec<&[Token]> = operands(&line[1..]);
+    let regs : Vec<bool> = ops.iter().map(|op| matches!(op, [tok] if is_register(tok))).collect();
+    let pseudo = pseudo::find_pseudo(&opts.instructions, m, &regs)?;
+    let value : Option<i64> = pseudo.imm.and_then(|i| ops.get(i))
+        .and_then(|op| symbols.early_value(&expr::parse(op, src).ok()?, scope, src).ok());
+    Some((pseudo, pseudo.choose(value)))
+}
+
+// Whether the profile (if any) allows an instruction. Those from opts.instructions have to be
+// listed in it like any other
+fn allowed(mnemonic : &str, opts : &Options) -> bool {
+    opts.profile.as_ref().is_none_or(|profile| profile.allows(mnemonic))
+}
+
+// Checks what the expansion of a pseudo-instruction can't: how it's written, that the profile has what
+// it turns into, how many operands it has and that its value fits in 32 bits.
+// Gives back the operands to put in the expansion
+fn check_pseudo<'a>(pseudo : &Pseudo, expansion : &pseudo::Expansion, line : &'a [Token], symbols : &Symbols, scope : usize,
+    opts : &Options, src : &str) -> Result<Vec<&'a [Token]>, AssembleError> {
+    let err = |kind : ErrorKind, span : Span| AssembleError::new(kind, span, src);
+    let TokenKind::Mnemonic(m) = &line[0].kind else { unreachable!() };
+    if opts.strict && m != pseudo.mnemonic {
+        return Err(err(ErrorKind::NotCanonical(m.clone(), String::from(pseudo.mnemonic)), line[0].span));
+    }
+    if let Some(profile) = &opts.profile {
+        let mut reals = expansion.lines.iter().filter_map(|text| text.split_whitespace().next());
+        if let Some(real) = reals.find(|real| !allowed(real, opts)) {
+            let kind = ErrorKind::PseudoNotInProfile(String::from(pseudo.mnemonic), String::from(real), profile.name.clone());
+            return Err(err(kind, line[0].span));
+        }
+    }
+    let ops : Vec<&[Token]> = operands(&line[1..]);
+    if ops.len() != pseudo.operands.len() {
+        let span = Span::covering(line);
+        return Err(err(ErrorKind::OperandCount(pseudo.mnemonic.to_uppercase(), pseudo.operands.len(), ops.len()), span));
+    }
+    for (&arg, &op) in pseudo.operands.iter().zip(&ops) {
+        if op.is_empty() { return Err(err(ErrorKind::MissingOperand, line[0].span)); }
+        if arg == pseudo::Arg::Reg && !matches!(op, [tok] if is_register(tok)) {
+            let span = Span::covering(op);
+            return Err(err(ErrorKind::WrongSyntax(pseudo.mnemonic.to_uppercase(), String::from(token_text(op, src))), span));
+        }
+    }
+    if let Some(op) = pseudo.imm.map(|i| ops[i]) {
+        let Operand::Expr(e) = parse_operand(op, false, line[0].span, src)? else {
+            return Err(err(ErrorKind::WrongSyntax(pseudo.mnemonic.to_uppercase(), String::from(token_text(op, src))), op[0].span));
+        };
+        let value = e.eval(&mut |name| symbols.value(name, scope), src)?;
+        fit(value, 32, value < 0, Span::covering(op), src)?;
+    }
+    Ok(ops)
+}
+
+// Encodes a single real instruction
+fn encode_instruction(line : &[Token], word_count : u32, symbols : &Symbols, scope : usize, opts : &Options, src : &str) -> Result<u32, AssembleError> {
+    let err = |kind : ErrorKind, span : Span| AssembleError::new(kind, span, src);
+    let TokenKind::Mnemonic(m) = &line[0].kind else { unreachable!() };
+    let spec : &InstrSpec = match isa::find_instruction(&opts.instructions, m, operands(&line[1..]).len()) {
+        Some(spec) if opts.strict && *m != spec.mnemonic => {
+            return Err(err(ErrorKind::NotCanonical(m.clone(), spec.mnemonic.to_string()), line[0].span));
+        }
+        Some(spec) => spec,
+        None => return Err(err(ErrorKind::UnknownInstruction(m.clone()), line[0].span)),
+    };
+    if let Some(profile) = opts.profile.as_ref().filter(|_| !allowed(&spec.mnemonic, opts)) {
+        return Err(err(ErrorKind::NotInProfile(spec.mnemonic.to_string(), profile.name.clone()), line[0].span));
+    }
+    let (instr, spans) = parse_instruction(spec, line, word_count, symbols, scope, opts, src)?;
+    // Point at the operand that filled the field, or the whole instruction if it came from the table
+    instr.encode().map_err(|e| err(ErrorKind::FieldOverflow(e), spans.get(e.field).copied().unwrap_or(line[0].span)))
+}
+
+// Resolves the operands after the mnemonic (line[0]) into the fields of the instruction,
+// also returning which operand filled each field for error messages
+fn parse_instruction(spec : &InstrSpec, line : &[Token], word_count : u32,
+    symbols : &Symbols, scope : usize, opts : &Options, src : &str)
+    -> Result<(Instruction, HashMap<&'static str, Span>), AssembleError> {
+    let err = |kind : ErrorKind, span : Span| AssembleError::new(kind, span, src);
+    let ops : Vec<&[Token]> = operands(&line[1..]);
+    if ops.len() != spec.operands.len() {
+        let span = Span::covering(line);
+        return Err(err(ErrorKind::OperandCount(spec.mnemonic.to_uppercase(), spec.operands.len(), ops.len()), span));
+    }
+    // The immediate is sign or zero extended by the processor, depending on the instruction
+    let imm16 = |value : i64, span : Span| fit(value, 16, spec.extend == Extend::Sign, span, src);
+
+    let model : &AddressModel = &opts.address_model;
+    // The base register and offset to load or store at value, which is relative to $gp instead of $zero
+    // when it comes from a data label and the model has a $gp
+    let data_offset = |value : i64, data : bool, bytes : bool, span : Span| match model.gp.filter(|_| data) {
+        Some(gp) => Ok((GP, imm16(value - if bytes { model.bytes(gp as i64) } else { gp as i64 }, span)?)),
+        None if data => imm16(value, span).map(|imm| (spec.rs, imm)).map_err(|_| err(ErrorKind::DataOutOfReach(value), span)),
+        None => Ok((spec.rs, imm16(value, span)?)),
+    };
+
+    let (mut rs, mut rt, mut rd, mut shamt, mut imm, mut target) = (spec.rs, spec.rt, spec.rd, 0, 0, 0);
+    let mut spans : HashMap<&'static str, Span> = HashMap::new();
+    for (&slot, &op) in spec.operands.iter().zip(&ops) {
+        let operand : Operand = parse_operand(op, matches!(slot, Slot::Mem | Slot::ByteMem), line[0].span, src)?;
+        // Byte and halfword accesses need the byte address of a data label, the rest its address in the model.
+        // Also says whether a data label was used
+        let bytes : bool = slot == Slot::ByteMem;
+        let eval_data = |e : &Expr| -> Result<(i64, bool), AssembleError> {
+            let mut data : bool = false;
+            let value = e.eval(&mut |name| match symbols.data_labels.get(name) {
+                Some(&offset) => {
+                    data = true;
+                    Some(if bytes { model.data_byte_address(offset) } else { model.data_address(offset) })
+                }
+                None => symbols.value(name, scope),
+            }, src)?;
+            Ok((value, data))
+        };
+        let eval = |e : &Expr| eval_data(e).map(|(value, _)| value);
+        let span = Span::covering(op);
+        match (slot, operand) {
+            // A bare name is a register too, but only where a register goes
+            (Slot::Rd | Slot::Rs | Slot::Rt, Operand::Reg(_) | Operand::Expr(Expr::Symbol(..))) if is_register(&op[0]) => {
+                let reg = register(&op[0], opts.strict, src)?;
+                match slot {
+                    Slot::Rd => rd = reg,
+                    Slot::Rs => rs = reg,
+                    _ => rt = reg,
+                }
+            }
+            (Slot::Fd | Slot::Fs | Slot::Ft, Operand::Reg(tok)) => {
+                let reg = float_register(tok, opts.strict, src)?;
+                match slot {
+                    Slot::Fd => shamt = reg,
+                    Slot::Fs => rd = reg,
+                    _ => rt = reg,
+                }
+            }
+            (Slot::Cop0, Operand::Reg(Token { kind: TokenKind::Register(name), .. })) => match name.parse::<u32>() {
+                Ok(reg) if reg < 32 => rd = reg,
+                _ => return Err(err(ErrorKind::NotCop0Register(name.clone()), span)),
+            },
+            (Slot::Shamt, Operand::Expr(e)) => shamt = fit(eval(&e)?, 5, false, span, src)?,
+            (Slot::Imm, Operand::Expr(e)) => imm = imm16(eval(&e)?, span)?,
+            (Slot::Mem | Slot::ByteMem, Operand::Expr(e)) => {
+                let (value, data) = eval_data(&e)?;
+                (rs, imm) = data_offset(value, data, bytes, span)?;
+            }
+            (Slot::Mem | Slot::ByteMem, Operand::Offset(offset, base)) => {
+                let offset : i64 = match offset {
+                    Some(e) => eval(&e)?,
+                    None => 0,
+                };
+                match &base.kind {
+                    TokenKind::Identifier(label) if symbols.data_labels.contains_key(label) || !is_register(base) => {
+                        let (value, data) = eval_data(&Expr::Symbol(label.clone(), base.span))?;
+                        (rs, imm) = data_offset(value + offset, data, bytes, span)?;
+                    }
+                    _ => {
+                        rs = register(base, opts.strict, src)?;
+                        spans.insert("rs", base.span);
+                        imm = imm16(offset, span)?;
+                    }
+                }
+            }
+            // Branches always take a signed offset in words from the next instruction
+            (Slot::Branch, Operand::Expr(e)) => {
+                let address = eval(&e)?;
+                let offset = match address - model.text_address(word_count) {
+                    delta if model.addressing == Addressing::Byte && delta % 4 != 0 => return Err(err(ErrorKind::NotWordAligned(address), span)),
+                    delta if model.addressing == Addressing::Byte => delta >> 2,
+                    delta => delta,
+                };
+                imm = fit(offset, 16, true, span, src)?;
+            }
+            (Slot::Target, Operand::Expr(e)) => target = jump_target(model, eval(&e)?, model.text_address(word_count)).map_err(|kind| err(kind, span))?,
+            _ => return Err(err(ErrorKind::WrongSyntax(spec.mnemonic.to_uppercase(), String::from(token_text(op, src))), span)),
+        }
+        let field = match slot {
+            Slot::Rd | Slot::Cop0 | Slot::Fs => "rd",
+            Slot::Rs => "rs",
+            Slot::Rt | Slot::Ft => "rt",
+            Slot::Shamt | Slot::Fd => "shamt",
+            Slot::Imm | Slot::Branch | Slot::Mem | Slot::ByteMem => "imm",
+            Slot::Target => "target",
+        };
+        spans.insert(field, span);
+    }
+
+    let instr = match spec.format {
+        Format::R => Instruction::R { opcode: spec.opcode, rs, rt, rd, shamt, funct: spec.funct },
+        Format::I => Instruction::I { opcode: spec.opcode, rs, rt, imm },
+        Format::J => Instruction::J { opcode: spec.opcode, target },
+    };
+    Ok((instr, spans))
+}
+
+// The target field of a j or jal to address, which only replaces the lower 26 bits of the word address
+// of the instruction after it (next)
+fn jump_target(model : &AddressModel, address : i64, next : i64) -> Result<u32, ErrorKind> {
+    let (word, next_word) = match model.addressing {
+        Addressing::Word => (address, next),
+        Addressing::Byte if address % 4 != 0 => return Err(ErrorKind::NotWordAligned(address)),
+        Addressing::Byte => (address >> 2, next >> 2),
+    };
+    if word < 0 || word >> 26 != next_word >> 26 {
+        return Err(ErrorKind::JumpOutOfRegion(address));
+    }
+    Ok((word & 0x3FF_FFFF) as u32)
+}
+
+// Checks value fits in a field of bits (as a two's complement number if signed),
+// giving back just those bits
+fn fit(value : i64, bits : u32, signed : bool, span : Span, src : &str) -> Result<u32, AssembleError> {
+    let (min, max) = if signed { (-(1 << (bits - 1)), (1 << (bits - 1)) - 1) } else { (0, (1 << bits) - 1) };
+    if value < min || value > max {
+        return Err(AssembleError::new(ErrorKind::OutOfRange(value, min, max), span, src));
+    }
+    Ok(value as u32 & ((1u64 << bits) - 1) as u32)
+}
+
+// Resolves $f0 to $f31, where strict mode only takes the lowercase $f and no leading zeros
+fn float_register(tok : &Token, strict : bool, src : &str) -> Result<u32, AssembleError> {
+    let TokenKind::Register(name) = &tok.kind else { unreachable!() };
+    let reg = isa::parse_float_register(name).ok_or_else(|| AssembleError::new(ErrorKind::NotFloatRegister(name.clone()), tok.span, src))?;
+    if strict && *name != format!("f{}", reg) {
+        return Err(AssembleError::new(ErrorKind::NotCanonical(format!("${}", name), format!("$f{}", reg)), tok.span, src));
+    }
+    Ok(reg)
+}
+
+fn is_register(tok : &Token) -> bool {
+    match &tok.kind {
+        TokenKind::Register(_) => true,
+        TokenKind::Identifier(name) => isa::parse_register(name).is_some(),
+        _ => false,
+    }
+}
+
+// Resolves $name, $number or a bare name to its register number,
+// where strict mode only takes the canonical lowercase $name
+fn register(tok : &Token, strict : bool, src : &str) -> Result<u32, AssembleError> {
+    let (name, bare) = match &tok.kind {
+        TokenKind::Register(name) => (name, false),
+        TokenKind::Identifier(name) => (name, true),
+        _ => unreachable!(),
+    };
+    let reg = isa::parse_register(name).ok_or_else(|| AssembleError::new(ErrorKind::UnknownRegister(name.clone()), tok.span, src))?;
+    let canonical = isa::REGISTER_NAMES[reg as usize];
+    if strict && (bare || name != canonical) {
+        let written = String::from(token_text(std::slice::from_ref(tok), src));
+        return Err(AssembleError::new(ErrorKind::NotCanonical(written, format!("${}", canonical)), tok.span, src));
+    }
+    Ok(reg)
+}
+
+// Splits the tokens after a mnemonic or directive at each comma
+fn operands(tokens : &[Token]) -> Vec<&[Token]> {
+    if tokens.is_empty() { return Vec::new(); }
+    tokens.split(|t| t.kind == TokenKind::Comma).collect()
+}
+
+// The source text covered by a run of tokens, used for error messages
+fn token_text<'a>(tokens : &[Token], src : &'a str) -> &'a str {
+    match (tokens.first(), tokens.last()) {
+        (Some(first), Some(last)) => &src[first.span.start..last.span.end],
+        _ => "",
+    }
+}
+
+// at is where to point when the operand is missing altogether (the mnemonic or directive).
+// offset(base) is only looked for when mem is set, anywhere else (x) is just an expression
+fn parse_operand<'a>(op : &'a [Token], mem : bool, at : Span, src : &str) -> Result<Operand<'a>, AssembleError> {
+    match op {
+        [] => return Err(AssembleError::new(ErrorKind::MissingOperand, at, src)),
+        [tok @ Token { kind: TokenKind::Register(_), .. }] => return Ok(Operand::Reg(tok)),
+        _ => (),
+    }
+    if let (true, [offset @ .., Token { kind: TokenKind::LParen, .. }, base, Token { kind: TokenKind::RParen, .. }]) = (mem, op) {
+        match (&base.kind, offset) {
+            (TokenKind::Register(_) | TokenKind::Identifier(_), []) => return Ok(Operand::Offset(None, base)),
+            (TokenKind::Register(_), _) => return Ok(Operand::Offset(Some(expr::parse(offset, src)?), base)),
+            // Something like (a)+(b) isn't an offset, and is taken as a whole below
+            (TokenKind::Identifier(_), _) => if let Ok(e) = expr::parse(offset, src) {
+                return Ok(Operand::Offset(Some(e), base));
+            },
+            _ => (),
+        }
+    }
+    Ok(Operand::Expr(expr::parse(op, src)?))
+}
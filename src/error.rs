@@ -0,0 +1,247 @@
+// Everything that can go wrong while assembling a file, reported as
+// ERROR(line:col): message
+//     offending source line
+//     ^^^^ (under the offending token)
+
+use std::fmt;
+use crate::instruction::FieldError;
+use crate::lexer::Span;
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum ErrorKind {
+    // Lexer
+    UnexpectedChar(char),
+    InvalidNumber(String),
+    UnterminatedString,
+    UnknownEscape,
+    BadCharacter,
+    MissingRegisterName,
+    MissingDirectiveName,
+    // Labels, constants and sections
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+    ExpectedConstantName(String),
+    ConstantRedefined(String, u32),
+    DefinedOnCommandLine(String),
+    ConstantIsLabel(String, u32),
+    CircularConstant(String),
+    // Macros
+    ExpectedMacroName,
+    BadMacroParam,
+    UnknownMacroParam(String),
+    UnterminatedMacro(String),
+    NestedMacro,
+    StrayEndMacro,
+    DuplicateMacro(String, usize),
+    MacroArgCount(String, usize),
+    MacroTooDeep(String, usize),
+    // Files
+    ExpectedIncludeFile,
+    IncludeNotFound(String),
+    IncludeCycle(String),
+    CantRead(String, String),
+    // Conditional assembly
+    StrayConditional(String),
+    ElseAfterElse(String),
+    UnterminatedIf(String),
+    ExpectedConditionName(String),
+    NotKnownYet(String),
+    MissingEntry(String),
+    EntryNotCode(String),
+    WrongSection(String, &'static str),
+    ExpectedStatement,
+    // Directives
+    UnsupportedDirective(String),
+    BadAlign(i64),
+    NegativeSpace(i64),
+    NotImmediate(String),
+    // Instructions
+    UnknownInstruction(String),
+    UnknownRegister(String),
+    NotCop0Register(String),
+    NotFloatRegister(String),
+    NotCanonical(String, String),
+    WrongSyntax(String, String),
+    MissingOperand,
+    BadExpression,
+    DivisionByZero,
+    BadShift(i64),
+    OperandCount(String, usize, usize),
+    FieldOverflow(FieldError),
+    OutOfRange(i64, i64, i64),
+    NotWordAligned(i64),
+    JumpOutOfRegion(i64),
+    DataOutOfReach(i64),
+    // Instruction set files
+    BadIsaMnemonic(String),
+    UnknownIsaField(String),
+    BadIsaValue(String, String),
+    MissingIsaField(String),
+    SlotNotInFormat(String, String),
+    DuplicateInstruction(String, usize),
+    // Profiles
+    NotInProfile(String, String),
+    PseudoNotInProfile(String, String, String),
+    StubNotInProfile(String),
+    PseudoInProfile(String),
+    // Memory sizes
+    TooManyInstructions(u32),
+    TooMuchData(u32),
+    KernelOverlap(u32),
+    VectorOutOfMemory(u32, u32),
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::UnexpectedChar(c) => write!(f, "Unexpected character '{}'!", c),
+            ErrorKind::InvalidNumber(s) => write!(f, "{} is not a valid number!", s),
+            ErrorKind::UnterminatedString => write!(f, "Unterminated string!"),
+            ErrorKind::UnknownEscape => write!(f, "Unknown escape sequence!"),
+            ErrorKind::BadCharacter => write!(f, "A character literal should be a single character between single quotes!"),
+            ErrorKind::MissingRegisterName => write!(f, "Expected a register name after '$'!"),
+            ErrorKind::MissingDirectiveName => write!(f, "Expected a directive name after '.'!"),
+            ErrorKind::DuplicateLabel(l) => write!(f, "The label {} was defined twice or more times!", l),
+            ErrorKind::UndefinedLabel(l) => write!(f, "Label {} does not exist!", l),
+            ErrorKind::ExpectedConstantName(d) => write!(f, "{} should be followed by the name of the constant and its value!", d),
+            ErrorKind::ConstantRedefined(c, line) => write!(f, "The constant {} was already defined on line {}! Only a constant made with .set can be given a new value, with .set again.", c, line),
+            ErrorKind::DefinedOnCommandLine(c) => write!(f, "The constant {} was already defined with -D!", c),
+            ErrorKind::ConstantIsLabel(c, line) => write!(f, "{} is both a constant and a label, which is defined on line {}!", c, line),
+            ErrorKind::CircularConstant(c) => write!(f, "The constant {} depends on itself!", c),
+            ErrorKind::ExpectedMacroName => write!(f, ".macro should be followed by the name of the macro!"),
+            ErrorKind::BadMacroParam => write!(f, "Macro parameters should be written like %name!"),
+            ErrorKind::UnknownMacroParam(p) => write!(f, "%{} is not a parameter of this macro!", p),
+            ErrorKind::UnterminatedMacro(m) => write!(f, "The macro {} has no .end_macro!", m),
+            ErrorKind::NestedMacro => write!(f, "A macro can't be defined inside another one!"),
+            ErrorKind::StrayEndMacro => write!(f, ".end_macro without a .macro before it!"),
+            ErrorKind::DuplicateMacro(m, n) => write!(f, "The macro {} with {} parameter(s) was defined twice or more times!", m, n),
+            ErrorKind::MacroArgCount(m, n) => write!(f, "There is no macro {} taking {} argument(s)!", m, n),
+            ErrorKind::MacroTooDeep(m, n) => write!(f, "Macro {} went more than {} calls deep, does it call itself forever?", m, n),
+            ErrorKind::ExpectedIncludeFile => write!(f, ".include should be followed by a file name between double quotes!"),
+            ErrorKind::IncludeNotFound(file) => write!(f, "Can't find {} next to this file or in any -I directory!", file),
+            ErrorKind::IncludeCycle(file) => write!(f, "{} ends up including itself!", file),
+            ErrorKind::CantRead(file, why) => write!(f, "Can't read {}: {}!", file, why),
+            ErrorKind::StrayConditional(d) => write!(f, "{} without an .if before it!", d),
+            ErrorKind::ElseAfterElse(d) => write!(f, "{} can't come after the .else of the same .if!", d),
+            ErrorKind::UnterminatedIf(d) => write!(f, "This {} has no .endif!", d),
+            ErrorKind::ExpectedConditionName(d) => write!(f, "{} should be followed by the name of a constant!", d),
+            ErrorKind::NotKnownYet(name) => write!(f, "{} has no value yet! Only -D constants and constants defined before this line without labels can be used here", name),
+            ErrorKind::MissingEntry(l) => write!(f, "The entry point {} was never defined!", l),
+            ErrorKind::EntryNotCode(l) => write!(f, "The entry point {} is a data label, it should be in the .text section!", l),
+            ErrorKind::WrongSection(s, section) => write!(f, "{} can only be used in the {} section!", s, section),
+            ErrorKind::ExpectedStatement => write!(f, "Expected a label, an instruction or a directive here!"),
+            ErrorKind::UnsupportedDirective(d) => write!(f, "Unsupported directive {}! Only .word, .half, .byte, .float, .double, .space and .align are implemented.", d),
+            ErrorKind::BadAlign(n) => write!(f, ".align {} is out of range, it should be between 0 and 3!", n),
+            ErrorKind::NegativeSpace(n) => write!(f, "Can't reserve {} bytes!", n),
+            ErrorKind::NotImmediate(s) => write!(f, "Unsupported token {}! Only numbers, labels and expressions can be used as data", s),
+            ErrorKind::UnknownInstruction(m) => write!(f, "Instruction {} is not implemented.", m),
+            ErrorKind::UnknownRegister(r) => write!(f, "Unknown register ${}!", r),
+            ErrorKind::NotFloatRegister(r) => write!(f, "${} is not a float register, they go from $f0 to $f31!", r),
+            ErrorKind::NotCop0Register(r) => write!(f, "${} is not a coprocessor 0 register, they are written as numbers like $12 for Status!", r),
+            ErrorKind::NotCanonical(found, canonical) => write!(f, "{} should be written as {} in strict mode!", found, canonical),
+            ErrorKind::WrongSyntax(m, s) => write!(f, "{} instruction has wrong syntax! This is wrong: {}", m, s),
+            ErrorKind::MissingOperand => write!(f, "Missing operand!"),
+            ErrorKind::BadExpression => write!(f, "Can't understand this expression!"),
+            ErrorKind::DivisionByZero => write!(f, "Division by zero!"),
+            ErrorKind::BadShift(n) => write!(f, "Can't shift by {}, it should be between 0 and 63!", n),
+            ErrorKind::OperandCount(m, expected, found) => write!(f, "{} takes {} operand(s), but {} were given!", m, expected, found),
+            ErrorKind::FieldOverflow(e) => write!(f, "{}", e),
+            ErrorKind::OutOfRange(value, min, max) => write!(f, "{} is out of range, it should be between {} and {}!", value, min, max),
+            ErrorKind::NotWordAligned(a) => write!(f, "{:#X} is not a multiple of 4, so there is no instruction there!", a),
+            ErrorKind::JumpOutOfRegion(a) => write!(f, "Can't jump to {:#X} from here! j and jal can only change the lower 26 bits of the word address", a),
+            ErrorKind::DataOutOfReach(a) => write!(f, "The data address {:#X} doesn't fit in a 16 bit offset! Load it with la first, or make data labels relative to $gp", a),
+            ErrorKind::BadIsaMnemonic(m) => write!(f, "{} is not a valid mnemonic!", m),
+            ErrorKind::UnknownIsaField(k) => write!(f, "Unknown field {}! Instructions are described with format=, opcode=, funct=, operands=, imm=, rs=, rt= and rd=", k),
+            ErrorKind::BadIsaValue(v, k) => write!(f, "{} is not a valid {}!", v, k),
+            ErrorKind::MissingIsaField(k) => write!(f, "This instruction is missing {}=!", k),
+            ErrorKind::SlotNotInFormat(s, format) => write!(f, "{}-Format instructions have no field for a {} operand!", format, s),
+            ErrorKind::DuplicateInstruction(m, n) => write!(f, "The instruction {} with {} operand(s) was described twice or more times!", m, n),
+            ErrorKind::NotInProfile(m, p) => write!(f, "{} is not supported by the {} profile!", m, p),
+            ErrorKind::PseudoNotInProfile(m, real, p) => write!(f, "{} turns into {} here, which is not supported by the {} profile!", m, real, p),
+            ErrorKind::StubNotInProfile(p) => write!(f, "The entry point isn't the first instruction, so a j to it goes first, but the {} profile has no j!", p),
+            ErrorKind::PseudoInProfile(m) => write!(f, "{} is a pseudo-instruction, list the instructions it turns into instead!", m),
+            ErrorKind::TooManyInstructions(n) => write!(f, "Too many instructions! There can be at most {} instructions!", n),
+            ErrorKind::TooMuchData(n) => write!(f, "Too much data! There can be at most {} words of data!", n),
+            ErrorKind::KernelOverlap(n) => write!(f, "The .text code runs past word {}, where the .ktext code starts!", n),
+            ErrorKind::VectorOutOfMemory(n, depth) => write!(f, "The exception vector is word {}, but there are only {} words of instructions!", n, depth),
+        }
+    }
+}
+
+// A macro call that an error came out of
+#[derive(Debug,Clone,PartialEq)]
+pub struct MacroCall {
+    pub name : String,
+    pub span : Span,
+    pub snippet : String,
+    pub file : Option<String>,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct AssembleError {
+    pub kind : ErrorKind,
+    pub span : Option<Span>, // None for errors about the whole file
+    pub context : Box<Context>, // Boxed, since errors are returned by value everywhere
+}
+
+// What is shown around the message
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct Context {
+    pub snippet : String,    // The source line the span points into
+    pub file : Option<String>, // The file the span is in, when it's known
+    pub calls : Vec<MacroCall>, // When span is inside a macro body, the calls that got there, innermost first
+}
+
+// The source line a span points into
+pub fn snippet(src : &str, span : Span) -> String {
+    let line_start = src[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = src[span.start..].find('\n').map_or(src.len(), |i| span.start + i);
+    String::from(src[line_start..line_end].trim_end_matches('\r'))
+}
+
+impl AssembleError {
+    pub fn new(kind : ErrorKind, span : Span, src : &str) -> AssembleError {
+        AssembleError { kind, span: Some(span), context: Box::new(Context { snippet: snippet(src, span), ..Context::default() }) }
+    }
+
+    pub fn global(kind : ErrorKind) -> AssembleError {
+        AssembleError { kind, span: None, context: Box::default() }
+    }
+}
+
+// line:col, after the file name if there is one
+fn location(file : &Option<String>, span : Span) -> String {
+    match file {
+        Some(file) => format!("{}:{}:{}", file, span.line, span.col),
+        None => format!("{}:{}", span.line, span.col),
+    }
+}
+
+// Writes the snippet with a caret line under the span
+fn underline(f : &mut fmt::Formatter, snippet : &str, span : Span) -> fmt::Result {
+    writeln!(f, "    {}", snippet)?;
+    // Copy the tabs before the token so the caret lines up however the terminal renders them
+    let pad : String = snippet.chars().take(span.col as usize - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+    let width = snippet.chars().skip(span.col as usize - 1).count()
+        .min(span.end - span.start).max(1);
+    write!(f, "    {}{}", pad, "^".repeat(width))
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let span = match self.span {
+            Some(span) => span,
+            None => return write!(f, "ERROR: {}", self.kind),
+        };
+        writeln!(f, "ERROR({}): {}", location(&self.context.file, span), self.kind)?;
+        underline(f, &self.context.snippet, span)?;
+        for call in &self.context.calls {
+            writeln!(f, "\n  in macro {}, called at {}", call.name, location(&call.file, call.span))?;
+            underline(f, &call.snippet, call.span)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AssembleError {}
@@ -0,0 +1,251 @@
+// Integer expressions in operands and data, like table+8, N*4 or (END-START)/4.
+// They are parsed when the line is read, but only evaluated once every label has an address.
+
+use crate::error::{AssembleError, ErrorKind};
+use crate::lexer::{Span, Token, TokenKind};
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum BinOp { Mul, Div, Rem, Add, Sub, Shl, Shr, Lt, Le, Gt, Ge, Eq, Ne, And, Xor, Or, LogAnd, LogOr }
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum UnOp { Neg, Not, LogNot, Hi, Lo }
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Expr {
+    Num(i64),
+    Symbol(String, Span),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>, Span), // The span is the operator's
+}
+
+// Lower binds looser, like in C
+fn precedence(op : BinOp) -> u8 {
+    match op {
+        BinOp::LogOr => 1,
+        BinOp::LogAnd => 2,
+        BinOp::Or => 3,
+        BinOp::Xor => 4,
+        BinOp::And => 5,
+        BinOp::Eq | BinOp::Ne => 6,
+        BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 7,
+        BinOp::Shl | BinOp::Shr => 8,
+        BinOp::Add | BinOp::Sub => 9,
+        BinOp::Mul | BinOp::Div | BinOp::Rem => 10,
+    }
+}
+
+fn binary_op(kind : &TokenKind) -> Option<BinOp> {
+    match kind {
+        TokenKind::Star => Some(BinOp::Mul),
+        TokenKind::Slash => Some(BinOp::Div),
+        TokenKind::Percent => Some(BinOp::Rem),
+        TokenKind::Plus => Some(BinOp::Add),
+        TokenKind::Minus => Some(BinOp::Sub),
+        TokenKind::Shl => Some(BinOp::Shl),
+        TokenKind::Shr => Some(BinOp::Shr),
+        TokenKind::Amp => Some(BinOp::And),
+        TokenKind::Caret => Some(BinOp::Xor),
+        TokenKind::Pipe => Some(BinOp::Or),
+        TokenKind::Lt => Some(BinOp::Lt),
+        TokenKind::Le => Some(BinOp::Le),
+        TokenKind::Gt => Some(BinOp::Gt),
+        TokenKind::Ge => Some(BinOp::Ge),
+        TokenKind::EqEq => Some(BinOp::Eq),
+        TokenKind::NotEq => Some(BinOp::Ne),
+        TokenKind::AndAnd => Some(BinOp::LogAnd),
+        TokenKind::OrOr => Some(BinOp::LogOr),
+        _ => None,
+    }
+}
+
+// Parsing and evaluating both recurse into the expression, so one this big is an error
+// before it can run out of stack. Every operand, parenthesis and unary operator counts
+const MAX_TERMS : usize = 256;
+
+struct Parser<'a> {
+    tokens : &'a [Token],
+    pos : usize,
+    src : &'a str,
+    terms : usize, // Counted up to MAX_TERMS
+}
+
+// Parses all of tokens as one expression
+pub fn parse(tokens : &[Token], src : &str) -> Result<Expr, AssembleError> {
+    let mut parser = Parser { tokens, pos: 0, src, terms: 0 };
+    let expr = parser.expr(0)?;
+    match tokens.get(parser.pos) {
+        Some(tok) => Err(AssembleError::new(ErrorKind::BadExpression, tok.span, src)),
+        None => Ok(expr),
+    }
+}
+
+impl Parser<'_> {
+    fn error(&self) -> AssembleError {
+        // Past the end, point at the last token since that's where something is missing
+        let tok = self.tokens.get(self.pos).or(self.tokens.last());
+        AssembleError::new(ErrorKind::BadExpression, tok.map(|t| t.span).unwrap_or_default(), self.src)
+    }
+
+    fn peek(&self) -> Option<&TokenKind> { self.tokens.get(self.pos).map(|t| &t.kind) }
+
+    fn expect(&mut self, kind : TokenKind) -> Result<(), AssembleError> {
+        if self.peek() != Some(&kind) { return Err(self.error()); }
+        self.pos += 1;
+        Ok(())
+    }
+
+    // Binary operators binding tighter than min_prec
+    fn expr(&mut self, min_prec : u8) -> Result<Expr, AssembleError> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.peek().and_then(binary_op) {
+            if precedence(op) <= min_prec { break; }
+            let span = self.tokens[self.pos].span;
+            self.pos += 1;
+            let rhs = self.expr(precedence(op))?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs), span);
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, AssembleError> {
+        if self.terms == MAX_TERMS { return Err(self.error()); }
+        self.terms += 1;
+        self.operand()
+    }
+
+    // A number, name, parenthesized expression or unary operator applied to one of those
+    fn operand(&mut self) -> Result<Expr, AssembleError> {
+        let Some(tok) = self.tokens.get(self.pos) else { return Err(self.error()) };
+        self.pos += 1;
+        Ok(match &tok.kind {
+            TokenKind::Integer(v) => Expr::Num(*v),
+            TokenKind::Identifier(name) => Expr::Symbol(name.clone(), tok.span),
+            TokenKind::Plus => self.unary()?,
+            TokenKind::Minus => Expr::Unary(UnOp::Neg, Box::new(self.unary()?)),
+            TokenKind::Tilde => Expr::Unary(UnOp::Not, Box::new(self.unary()?)),
+            TokenKind::Bang => Expr::Unary(UnOp::LogNot, Box::new(self.unary()?)),
+            TokenKind::LParen => {
+                let expr = self.expr(0)?;
+                self.expect(TokenKind::RParen)?;
+                expr
+            }
+            // %hi(expr) and %lo(expr)
+            TokenKind::Percent => {
+                let op = match self.peek() {
+                    Some(TokenKind::Identifier(name)) if name.eq_ignore_ascii_case("hi") => UnOp::Hi,
+                    Some(TokenKind::Identifier(name)) if name.eq_ignore_ascii_case("lo") => UnOp::Lo,
+                    _ => return Err(self.error()),
+                };
+                self.pos += 1;
+                self.expect(TokenKind::LParen)?;
+                let expr = self.expr(0)?;
+                self.expect(TokenKind::RParen)?;
+                Expr::Unary(op, Box::new(expr))
+            }
+            _ => {
+                self.pos -= 1;
+                return Err(self.error());
+            }
+        })
+    }
+}
+
+impl Expr {
+    // symbol gives the value of each name, or None when it was never defined
+    pub fn eval(&self, symbol : &mut dyn FnMut(&str) -> Option<i64>, src : &str) -> Result<i64, AssembleError> {
+        Ok(match self {
+            Expr::Num(v) => *v,
+            Expr::Symbol(name, span) => match symbol(name) {
+                Some(v) => v,
+                None => return Err(AssembleError::new(ErrorKind::UndefinedLabel(name.clone()), *span, src)),
+            },
+            Expr::Unary(op, e) => {
+                let v = e.eval(symbol, src)?;
+                match op {
+                    UnOp::Neg => v.wrapping_neg(),
+                    UnOp::Not => !v,
+                    UnOp::LogNot => (v == 0) as i64,
+                    // %hi is rounded up when %lo is negative, since %lo gets sign extended when added back
+                    UnOp::Hi => (v.wrapping_add(0x8000) >> 16) & 0xFFFF,
+                    UnOp::Lo => (v & 0xFFFF) as i16 as i64,
+                }
+            }
+            Expr::Binary(op, lhs, rhs, span) => {
+                let (l, r) = (lhs.eval(symbol, src)?, rhs.eval(symbol, src)?);
+                let err = |kind : ErrorKind| Err(AssembleError::new(kind, *span, src));
+                match op {
+                    BinOp::Mul => l.wrapping_mul(r),
+                    BinOp::Div | BinOp::Rem if r == 0 => return err(ErrorKind::DivisionByZero),
+                    BinOp::Div => l.wrapping_div(r),
+                    BinOp::Rem => l.wrapping_rem(r),
+                    BinOp::Add => l.wrapping_add(r),
+                    BinOp::Sub => l.wrapping_sub(r),
+                    BinOp::Shl | BinOp::Shr if !(0..64).contains(&r) => return err(ErrorKind::BadShift(r)),
+                    BinOp::Shl => l << r,
+                    BinOp::Shr => l >> r,
+                    BinOp::And => l & r,
+                    BinOp::Xor => l ^ r,
+                    BinOp::Or => l | r,
+                    // Comparisons and logic give 1 for true and 0 for false, like in C
+                    BinOp::Lt => (l < r) as i64,
+                    BinOp::Le => (l <= r) as i64,
+                    BinOp::Gt => (l > r) as i64,
+                    BinOp::Ge => (l >= r) as i64,
+                    BinOp::Eq => (l == r) as i64,
+                    BinOp::Ne => (l != r) as i64,
+                    BinOp::LogAnd => (l != 0 && r != 0) as i64,
+                    BinOp::LogOr => (l != 0 || r != 0) as i64,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+
+    fn eval(src : &str) -> Result<i64, ErrorKind> {
+        let tokens = lexer::tokenize(src, &mut Vec::new());
+        // The lexer takes the first word for a mnemonic, so expressions are read after one
+        let tokens : Vec<Token> = tokens.into_iter().skip(1).filter(|t| t.kind != TokenKind::Newline).collect();
+        let e = parse(&tokens, src).map_err(|e| e.kind)?;
+        e.eval(&mut |name| if name == "N" { Some(4) } else { None }, src).map_err(|e| e.kind)
+    }
+
+    #[test]
+    fn follows_c_precedence() {
+        assert_eq!(eval("x 1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("x (1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("x 1 << 4 | 3"), Ok(19));
+        assert_eq!(eval("x N * 4 - -1"), Ok(17));
+        assert_eq!(eval("x 1 < 2 && !0"), Ok(1));
+        assert_eq!(eval("x ~0 ^ 1"), Ok(-2));
+    }
+
+    #[test]
+    fn splits_hi_and_lo() {
+        assert_eq!(eval("x %hi(0x1234_8000)"), Ok(0x1235));
+        assert_eq!(eval("x %lo(0x1234_8000)"), Ok(-0x8000));
+        assert_eq!(eval("x (%hi(0x1234_8000) << 16) + %lo(0x1234_8000)"), Ok(0x1234_8000));
+        // Wraps instead of overflowing
+        assert_eq!(eval("x %hi(0x7FFF_FFFF_FFFF_FFFF)"), Ok(0));
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert_eq!(eval("x 1 / (N - 4)"), Err(ErrorKind::DivisionByZero));
+        assert_eq!(eval("x 1 << 64"), Err(ErrorKind::BadShift(64)));
+        assert_eq!(eval("x M + 1"), Err(ErrorKind::UndefinedLabel(String::from("M"))));
+        assert_eq!(eval("x (1 + 2"), Err(ErrorKind::BadExpression));
+    }
+
+    #[test]
+    fn limits_the_size_of_an_expression() {
+        assert_eq!(eval(&format!("x {}1{}", "(".repeat(20_000), ")".repeat(20_000))), Err(ErrorKind::BadExpression));
+        assert_eq!(eval(&format!("x {}1", "-".repeat(20_000))), Err(ErrorKind::BadExpression));
+        assert_eq!(eval(&format!("x 0{}", "+1".repeat(20_000))), Err(ErrorKind::BadExpression));
+        assert_eq!(eval(&format!("x 0{}", "+1".repeat(200))), Ok(200));
+    }
+}
@@ -0,0 +1,76 @@
+// A parsed instruction, with every field already resolved to a number, and its encoding
+
+use std::fmt;
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum Instruction {
+    R { opcode : u32, rs : u32, rt : u32, rd : u32, shamt : u32, funct : u32 },
+    I { opcode : u32, rs : u32, rt : u32, imm : u32 },
+    J { opcode : u32, target : u32 },
+}
+
+// A field whose value doesn't fit in its bits
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct FieldError {
+    pub field : &'static str,
+    pub value : u32,
+    pub bits : u32,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} doesn't fit in the {} bits of the {} field!", self.value, self.bits, self.field)
+    }
+}
+
+fn field(field : &'static str, value : u32, bits : u32) -> Result<u32, FieldError> {
+    if value >> bits != 0 { return Err(FieldError { field, value, bits }); }
+    Ok(value)
+}
+
+impl Instruction {
+    pub fn encode(&self) -> Result<u32, FieldError> {
+        Ok(match *self {
+            Instruction::R { opcode, rs, rt, rd, shamt, funct } =>
+                field("opcode", opcode, 6)? << 26 |
+                field("rs", rs, 5)? << 21 |
+                field("rt", rt, 5)? << 16 |
+                field("rd", rd, 5)? << 11 |
+                field("shamt", shamt, 5)? << 6 |
+                field("funct", funct, 6)?,
+            Instruction::I { opcode, rs, rt, imm } =>
+                field("opcode", opcode, 6)? << 26 |
+                field("rs", rs, 5)? << 21 |
+                field("rt", rt, 5)? << 16 |
+                field("imm", imm, 16)?,
+            Instruction::J { opcode, target } =>
+                field("opcode", opcode, 6)? << 26 |
+                field("target", target, 26)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_each_format() {
+        let add = Instruction::R { opcode: 0, rs: 9, rt: 10, rd: 8, shamt: 0, funct: 0x20 };
+        assert_eq!(add.encode(), Ok(0x012A_4020));
+        let lw = Instruction::I { opcode: 0x23, rs: 29, rt: 8, imm: 0xFFFC };
+        assert_eq!(lw.encode(), Ok(0x8FA8_FFFC));
+        let j = Instruction::J { opcode: 2, target: 0x3FF_FFFF };
+        assert_eq!(j.encode(), Ok(0x0BFF_FFFF));
+    }
+
+    #[test]
+    fn rejects_fields_that_dont_fit() {
+        let r = Instruction::R { opcode: 0, rs: 32, rt: 0, rd: 0, shamt: 0, funct: 0 };
+        assert_eq!(r.encode(), Err(FieldError { field: "rs", value: 32, bits: 5 }));
+        let i = Instruction::I { opcode: 8, rs: 0, rt: 0, imm: 0x1_0000 };
+        assert_eq!(i.encode(), Err(FieldError { field: "imm", value: 0x1_0000, bits: 16 }));
+        let j = Instruction::J { opcode: 64, target: 0 };
+        assert_eq!(j.encode(), Err(FieldError { field: "opcode", value: 64, bits: 6 }));
+    }
+}
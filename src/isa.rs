@@ -0,0 +1,555 @@
+// The instruction set: which mnemonics exist, their opcode/funct numbers and how the
+// written operands map onto the instruction fields. Registers are here too, and so is
+// reading more instructions from a file.
+
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+use crate::error::{AssembleError, ErrorKind};
+use crate::lexer::{self, Span};
+use crate::pseudo;
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum Format { R, I, J }
+
+// One written operand and the field(s) it fills
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum Slot {
+    Rd, Rs, Rt,
+    Shamt,
+    Imm,    // Immediate value or a label's address
+    Branch, // Jump label, stored as the word offset from the next instruction
+    Mem,    // label, offset(label) or offset(register), filling rs and imm
+    ByteMem, // Like Mem, but data labels are worth their byte address instead of their word index
+    Cop0,   // Coprocessor 0 register, written as its number like $12, filling rd
+    Fd, Fs, Ft, // Float registers $f0 to $f31, filling shamt, rd and rt
+    Target, // Jump label, stored as its word index
+}
+
+// How the processor widens the 16 bit immediate to 32 bits
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum Extend { Sign, Zero }
+
+// Built-in instructions borrow their name and operands, the ones read from a file own them
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct InstrSpec {
+    pub mnemonic : Cow<'static, str>,
+    pub format : Format,
+    pub opcode : u32,
+    pub funct : u32, // R-Format only
+    pub extend : Extend, // I-Format only
+    pub operands : Cow<'static, [Slot]>,
+    pub rd : u32, // Value of rd when no operand fills it, like $ra for the short jalr
+    pub rt : u32, // Value of rt when no operand fills it, like the condition of REGIMM branches
+    pub rs : u32, // Value of rs when no operand fills it, like what a coprocessor 0 instruction does
+}
+
+const fn r(mnemonic : &'static str, funct : u32, operands : &'static [Slot]) -> InstrSpec {
+    InstrSpec { mnemonic: Cow::Borrowed(mnemonic), format: Format::R, opcode: 0x00, funct, extend: Extend::Sign, operands: Cow::Borrowed(operands), rd: 0, rt: 0, rs: 0 }
+}
+
+const fn i(mnemonic : &'static str, opcode : u32, operands : &'static [Slot]) -> InstrSpec {
+    InstrSpec { mnemonic: Cow::Borrowed(mnemonic), format: Format::I, opcode, funct: 0, extend: Extend::Sign, operands: Cow::Borrowed(operands), rd: 0, rt: 0, rs: 0 }
+}
+
+// Float instructions, R-Format under opcode 0x11 with the format of their operands in rs
+const fn f(mnemonic : &'static str, fmt : u32, funct : u32, operands : &'static [Slot]) -> InstrSpec {
+    InstrSpec { mnemonic: Cow::Borrowed(mnemonic), format: Format::R, opcode: 0x11, funct, extend: Extend::Sign, operands: Cow::Borrowed(operands), rd: 0, rt: 0, rs: fmt }
+}
+
+// Formats of float instructions: single, double and word (a 32 bit integer in a float register)
+const FMT_S : u32 = 0x10;
+const FMT_D : u32 = 0x11;
+const FMT_W : u32 = 0x14;
+
+const fn j(mnemonic : &'static str, opcode : u32) -> InstrSpec {
+    InstrSpec { mnemonic: Cow::Borrowed(mnemonic), format: Format::J, opcode, funct: 0, extend: Extend::Sign, operands: Cow::Borrowed(&[Slot::Target]), rd: 0, rt: 0, rs: 0 }
+}
+
+impl InstrSpec {
+    const fn with_rd(mut self, rd : u32) -> InstrSpec {
+        self.rd = rd;
+        self
+    }
+
+    const fn with_rt(mut self, rt : u32) -> InstrSpec {
+        self.rt = rt;
+        self
+    }
+
+    const fn with_rs(mut self, rs : u32) -> InstrSpec {
+        self.rs = rs;
+        self
+    }
+
+    const fn zero_extended(mut self) -> InstrSpec {
+        self.extend = Extend::Zero;
+        self
+    }
+
+    // Coprocessor 0 instructions are R-Format under opcode 0x10, telling what to do by rs
+    const fn cop0(mut self, rs : u32) -> InstrSpec {
+        self.opcode = 0x10;
+        self.rs = rs;
+        self
+    }
+
+    // MIPS32 added R-Format instructions under their own opcode instead of 0
+    const fn special2(mut self) -> InstrSpec {
+        self.opcode = 0x1C;
+        self
+    }
+}
+
+use Slot::*;
+
+pub const INSTRUCTIONS : &[InstrSpec] = &[
+    // I-Format Instructions
+    i("sw", 0x2B, &[Rt, Mem]),
+    i("lw", 0x23, &[Rt, Mem]),
+    i("lb", 0x20, &[Rt, ByteMem]),
+    i("lh", 0x21, &[Rt, ByteMem]),
+    i("lbu", 0x24, &[Rt, ByteMem]),
+    i("lhu", 0x25, &[Rt, ByteMem]),
+    i("sb", 0x28, &[Rt, ByteMem]),
+    i("sh", 0x29, &[Rt, ByteMem]),
+    i("addi", 0x08, &[Rt, Rs, Imm]),
+    i("addiu", 0x09, &[Rt, Rs, Imm]),
+    i("slti", 0x0A, &[Rt, Rs, Imm]),
+    // Also sign extended, the comparison is what's unsigned
+    i("sltiu", 0x0B, &[Rt, Rs, Imm]),
+    i("andi", 0x0C, &[Rt, Rs, Imm]).zero_extended(),
+    i("ori", 0x0D, &[Rt, Rs, Imm]).zero_extended(),
+    i("xori", 0x0E, &[Rt, Rs, Imm]).zero_extended(),
+    // Nothing gets extended, but the upper half is taken as an unsigned number all the same
+    i("lui", 0x0F, &[Rt, Imm]).zero_extended(),
+    // The first register goes in rt and the second in rs, like the first version of this assembler did
+    i("beq", 0x04, &[Rt, Rs, Branch]),
+    i("bne", 0x05, &[Rt, Rs, Branch]),
+    // Comparisons with zero. Opcode 1 (REGIMM) tells them apart by rt, and the ones ending in al
+    // also save the return address in $ra like jal
+    i("bltz", 0x01, &[Rs, Branch]),
+    i("bgez", 0x01, &[Rs, Branch]).with_rt(0x01),
+    i("bltzal", 0x01, &[Rs, Branch]).with_rt(0x10),
+    i("bgezal", 0x01, &[Rs, Branch]).with_rt(0x11),
+    i("blez", 0x06, &[Rs, Branch]),
+    i("bgtz", 0x07, &[Rs, Branch]),
+    // R-Format Instructions
+    r("and", 0x24, &[Rd, Rs, Rt]),
+    r("or", 0x25, &[Rd, Rs, Rt]),
+    r("xor", 0x26, &[Rd, Rs, Rt]),
+    r("nor", 0x27, &[Rd, Rs, Rt]),
+    r("add", 0x20, &[Rd, Rs, Rt]),
+    r("addu", 0x21, &[Rd, Rs, Rt]),
+    r("sub", 0x22, &[Rd, Rs, Rt]),
+    r("subu", 0x23, &[Rd, Rs, Rt]),
+    r("slt", 0x2A, &[Rd, Rs, Rt]),
+    r("sltu", 0x2B, &[Rd, Rs, Rt]),
+    r("srl", 0x02, &[Rd, Rt, Shamt]),
+    r("sll", 0x00, &[Rd, Rt, Shamt]),
+    r("sra", 0x03, &[Rd, Rt, Shamt]),
+    // Variable shifts take the amount from a register, written last
+    r("sllv", 0x04, &[Rd, Rt, Rs]),
+    r("srlv", 0x06, &[Rd, Rt, Rs]),
+    r("srav", 0x07, &[Rd, Rt, Rs]),
+    // The multiply/divide unit writes hi and lo, which are only read with mfhi and mflo
+    r("mult", 0x18, &[Rs, Rt]),
+    r("multu", 0x19, &[Rs, Rt]),
+    r("div", 0x1A, &[Rs, Rt]),
+    r("divu", 0x1B, &[Rs, Rt]),
+    r("mfhi", 0x10, &[Rd]),
+    r("mthi", 0x11, &[Rs]),
+    r("mflo", 0x12, &[Rd]),
+    r("mtlo", 0x13, &[Rs]),
+    r("madd", 0x00, &[Rs, Rt]).special2(),
+    r("mul", 0x02, &[Rd, Rs, Rt]).special2(),
+    r("msub", 0x04, &[Rs, Rt]).special2(),
+    // sll $zero, $zero, 0
+    r("nop", 0x00, &[]),
+    r("syscall", 0x0C, &[]),
+    r("break", 0x0D, &[]),
+    r("mfc0", 0x00, &[Rt, Cop0]).cop0(0x00),
+    r("mtc0", 0x00, &[Rt, Cop0]).cop0(0x04),
+    r("eret", 0x18, &[]).cop0(0x10),
+    r("jr", 0x08, &[Rs]),
+    r("jalr", 0x09, &[Rd, Rs]),
+    r("jalr", 0x09, &[Rs]).with_rd(31),
+    // Float instructions
+    f("add.s", FMT_S, 0x00, &[Fd, Fs, Ft]),
+    f("sub.s", FMT_S, 0x01, &[Fd, Fs, Ft]),
+    f("mul.s", FMT_S, 0x02, &[Fd, Fs, Ft]),
+    f("div.s", FMT_S, 0x03, &[Fd, Fs, Ft]),
+    f("add.d", FMT_D, 0x00, &[Fd, Fs, Ft]),
+    f("sub.d", FMT_D, 0x01, &[Fd, Fs, Ft]),
+    f("mul.d", FMT_D, 0x02, &[Fd, Fs, Ft]),
+    f("div.d", FMT_D, 0x03, &[Fd, Fs, Ft]),
+    // cvt.to.from, so the format in rs is the one converted from
+    f("cvt.s.d", FMT_D, 0x20, &[Fd, Fs]),
+    f("cvt.s.w", FMT_W, 0x20, &[Fd, Fs]),
+    f("cvt.d.s", FMT_S, 0x21, &[Fd, Fs]),
+    f("cvt.d.w", FMT_W, 0x21, &[Fd, Fs]),
+    f("cvt.w.s", FMT_S, 0x24, &[Fd, Fs]),
+    f("cvt.w.d", FMT_D, 0x24, &[Fd, Fs]),
+    // Comparisons set the condition flag that bc1t and bc1f branch on
+    f("c.eq.s", FMT_S, 0x32, &[Fs, Ft]),
+    f("c.lt.s", FMT_S, 0x3C, &[Fs, Ft]),
+    f("c.le.s", FMT_S, 0x3E, &[Fs, Ft]),
+    f("c.eq.d", FMT_D, 0x32, &[Fs, Ft]),
+    f("c.lt.d", FMT_D, 0x3C, &[Fs, Ft]),
+    f("c.le.d", FMT_D, 0x3E, &[Fs, Ft]),
+    f("mfc1", 0x00, 0x00, &[Rt, Fs]),
+    f("mtc1", 0x04, 0x00, &[Rt, Fs]),
+    i("lwc1", 0x31, &[Ft, Mem]),
+    i("swc1", 0x39, &[Ft, Mem]),
+    i("ldc1", 0x35, &[Ft, Mem]),
+    i("sdc1", 0x3D, &[Ft, Mem]),
+    i("bc1f", 0x11, &[Branch]).with_rs(0x08),
+    i("bc1t", 0x11, &[Branch]).with_rs(0x08).with_rt(1),
+    // J-Format instructions
+    j("j", 0x02),
+    j("jal", 0x03),
+];
+
+// Mnemonics are matched in any case. Some have more than one form, so this is the one
+// taking that many operands, or else the first one (which will then complain about the count).
+// Instructions in extra come first, so they take over built-in ones with the same mnemonic
+pub fn find_instruction<'a>(extra : &'a [InstrSpec], mnemonic : &str, operands : usize) -> Option<&'a InstrSpec> {
+    let named = |spec : &&InstrSpec| spec.mnemonic.eq_ignore_ascii_case(mnemonic);
+    let forms : Vec<&InstrSpec> = if extra.iter().any(|spec| named(&spec)) { extra.iter().rev().filter(named).collect() }
+        else { INSTRUCTIONS.iter().filter(named).collect() };
+    forms.iter().find(|spec| spec.operands.len() == operands).or(forms.first()).copied()
+}
+
+// Names of the operands in an instruction set file
+const SLOT_NAMES : &[(&str, Slot)] = &[
+    ("rd", Rd), ("rs", Rs), ("rt", Rt), ("shamt", Shamt), ("imm", Imm), ("branch", Branch),
+    ("mem", Mem), ("bytemem", ByteMem), ("cop0", Cop0), ("fd", Fd), ("fs", Fs), ("ft", Ft), ("target", Target),
+];
+
+// Which formats have the field an operand fills
+fn slot_fits(slot : Slot, format : Format) -> bool {
+    match slot {
+        Rs | Rt | Ft => format != Format::J,
+        Rd | Shamt | Cop0 | Fd | Fs => format == Format::R,
+        Imm | Branch | Mem | ByteMem => format == Format::I,
+        Target => format == Format::J,
+    }
+}
+
+// Each whitespace separated word of a line, with where it starts in the line
+fn words(line : &str) -> Vec<(usize, &str)> {
+    let mut words : Vec<(usize, &str)> = Vec::new();
+    let mut start : Option<usize> = None;
+    for (i, c) in line.char_indices().chain([(line.len(), ' ')]) {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some(i),
+            (true, Some(s)) => {
+                words.push((s, &line[s..i]));
+                start = None;
+            }
+            _ => (),
+        }
+    }
+    words
+}
+
+// Reads an instruction set file, one instruction per line like
+//     swap format=R opcode=0 funct=0x3F operands=rd,rs,rt
+//     led format=I opcode=0x3E operands=rs,imm imm=unsigned
+// where funct, operands, imm and the fixed register fields rs, rt and rd can be left out
+pub fn parse(text : &str) -> Result<Vec<InstrSpec>, Vec<AssembleError>> {
+    let mut specs : Vec<InstrSpec> = Vec::new();
+    let mut errors : Vec<AssembleError> = Vec::new();
+    let mut line_start : usize = 0;
+    for (n, line) in text.split('\n').enumerate() {
+        let base = line_start;
+        line_start += line.len() + 1;
+        let line = line.split('#').next().unwrap_or("");
+        let span = |start : usize, word : &str| Span {
+            line: n as u32 + 1, col: line[..start].chars().count() as u32 + 1,
+            start: base + start, end: base + start + word.len(), expansion: 0,
+        };
+        let err = |kind : ErrorKind, start : usize, word : &str| AssembleError::new(kind, span(start, word), text);
+        let words = words(line);
+        let Some(&(at, mnemonic)) = words.first() else { continue }; // Blank or only a comment
+        let mut chars = mnemonic.chars();
+        if !chars.next().is_some_and(|c| c.is_ascii_alphabetic()) || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+            errors.push(err(ErrorKind::BadIsaMnemonic(String::from(mnemonic)), at, mnemonic));
+            continue;
+        }
+        let mut spec = InstrSpec { mnemonic: Cow::Owned(mnemonic.to_lowercase()), format: Format::R, opcode: 0, funct: 0,
+            extend: Extend::Sign, operands: Cow::Owned(Vec::new()), rd: 0, rt: 0, rs: 0 };
+        let (mut format, mut opcode) = (None, None);
+        let line_errors : usize = errors.len();
+        let mut slots : Vec<(Slot, usize, &str)> = Vec::new();
+        for &(start, word) in &words[1..] {
+            let Some((key, value)) = word.split_once('=') else {
+                errors.push(err(ErrorKind::UnknownIsaField(String::from(word)), start, word));
+                continue;
+            };
+            let bad = || err(ErrorKind::BadIsaValue(String::from(value), String::from(key)), start, word);
+            // Numbers are written like in the source, and must fit their field
+            let number = |bits : u32| lexer::parse_integer(value).filter(|&v| v < 1 << bits).map(|v| v as u32).ok_or_else(bad);
+            let result = match key {
+                "format" => match value.to_ascii_uppercase().as_str() {
+                    "R" => Some(Format::R),
+                    "I" => Some(Format::I),
+                    "J" => Some(Format::J),
+                    _ => None,
+                }.ok_or_else(bad).map(|f| format = Some(f)),
+                "opcode" => number(6).map(|v| opcode = Some(v)),
+                "funct" => number(6).map(|v| spec.funct = v),
+                "rs" => number(5).map(|v| spec.rs = v),
+                "rt" => number(5).map(|v| spec.rt = v),
+                "rd" => number(5).map(|v| spec.rd = v),
+                "imm" => match value {
+                    "signed" => Some(Extend::Sign),
+                    "unsigned" => Some(Extend::Zero),
+                    _ => None,
+                }.ok_or_else(bad).map(|extend| spec.extend = extend),
+                "operands" => value.split(',').filter(|name| !name.is_empty()).try_for_each(|name| {
+                    match SLOT_NAMES.iter().find(|(slot_name, _)| slot_name.eq_ignore_ascii_case(name)) {
+                        Some(&(_, slot)) => {
+                            slots.push((slot, start, word));
+                            Ok(())
+                        }
+                        None => Err(err(ErrorKind::BadIsaValue(String::from(name), String::from("operand")), start, word)),
+                    }
+                }),
+                _ => Err(err(ErrorKind::UnknownIsaField(String::from(key)), start, word)),
+            };
+            if let Err(e) = result { errors.push(e); }
+        }
+        let (Some(format), Some(opcode)) = (format, opcode) else {
+            // A field with a bad value was already reported
+            if errors.len() == line_errors {
+                let missing = if format.is_none() { "format" } else { "opcode" };
+                errors.push(err(ErrorKind::MissingIsaField(String::from(missing)), at, mnemonic));
+            }
+            continue;
+        };
+        for &(slot, start, word) in &slots {
+            if !slot_fits(slot, format) {
+                let name = SLOT_NAMES.iter().find(|(_, s)| *s == slot).unwrap().0;
+                errors.push(err(ErrorKind::SlotNotInFormat(String::from(name), format!("{:?}", format)), start, word));
+            }
+        }
+        spec.format = format;
+        spec.opcode = opcode;
+        spec.operands = Cow::Owned(slots.iter().map(|&(slot, _, _)| slot).collect());
+        if specs.iter().any(|s| s.mnemonic == spec.mnemonic && s.operands.len() == spec.operands.len()) {
+            errors.push(err(ErrorKind::DuplicateInstruction(spec.mnemonic.to_string(), spec.operands.len()), at, mnemonic));
+            continue;
+        }
+        specs.push(spec);
+    }
+    if errors.is_empty() { Ok(specs) } else { Err(errors) }
+}
+
+// Reads the instruction set file at path, see parse for how it's written
+pub fn load(path : &Path) -> Result<Vec<InstrSpec>, Vec<AssembleError>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| vec![AssembleError::global(ErrorKind::CantRead(path.display().to_string(), e.to_string()))])?;
+    parse(&text).map_err(|mut errors| {
+        for e in &mut errors { e.context.file = Some(path.display().to_string()); }
+        errors
+    })
+}
+
+// The instructions a processor implements, which a program is checked against
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Profile {
+    pub name : String,
+    pub mnemonics : Vec<String>, // Lowercase
+}
+
+// What the single-cycle processor this was first made for implements
+const SINGLE_CYCLE_15 : &[&str] = &["lw", "sw", "addi", "beq", "bne", "and", "or", "add", "sub", "slt", "srl", "sll", "jr", "j", "jal"];
+// Built-in instructions that came after MIPS I, in MIPS II or MIPS32
+const AFTER_MIPS_I : &[&str] = &["mul", "madd", "msub", "eret", "ldc1", "sdc1"];
+
+impl Profile {
+    // The built-in profiles: single-cycle-15, mips-i and mips32r2 (every built-in instruction)
+    pub fn named(name : &str) -> Option<Profile> {
+        let all = INSTRUCTIONS.iter().map(|spec| &*spec.mnemonic);
+        let mut mnemonics : Vec<String> = match name {
+            "single-cycle-15" => SINGLE_CYCLE_15.iter().map(|&m| String::from(m)).collect(),
+            "mips-i" => all.filter(|m| !AFTER_MIPS_I.contains(m)).map(String::from).collect(),
+            "mips32r2" => all.map(String::from).collect(),
+            _ => return None,
+        };
+        mnemonics.dedup();
+        Some(Profile { name: String::from(name), mnemonics })
+    }
+
+    pub fn allows(&self, mnemonic : &str) -> bool {
+        self.mnemonics.iter().any(|m| m.eq_ignore_ascii_case(mnemonic))
+    }
+}
+
+// Reads a profile file, the mnemonics it allows separated by spaces or new lines.
+// Each one has to be a real instruction, either built-in or in extra
+pub fn parse_profile(name : &str, text : &str, extra : &[InstrSpec]) -> Result<Profile, Vec<AssembleError>> {
+    let mut mnemonics : Vec<String> = Vec::new();
+    let mut errors : Vec<AssembleError> = Vec::new();
+    let mut line_start : usize = 0;
+    for (n, line) in text.split('\n').enumerate() {
+        let base = line_start;
+        line_start += line.len() + 1;
+        let line = line.split('#').next().unwrap_or("");
+        for (start, word) in words(line) {
+            let span = Span { line: n as u32 + 1, col: line[..start].chars().count() as u32 + 1,
+                start: base + start, end: base + start + word.len(), expansion: 0 };
+            if find_instruction(extra, word, 0).is_some() {
+                mnemonics.push(word.to_lowercase());
+                continue;
+            }
+            let kind = if pseudo::PSEUDOS.iter().any(|p| p.mnemonic.eq_ignore_ascii_case(word)) { ErrorKind::PseudoInProfile(String::from(word)) }
+                else { ErrorKind::UnknownInstruction(String::from(word)) };
+            errors.push(AssembleError::new(kind, span, text));
+        }
+    }
+    if errors.is_empty() { Ok(Profile { name: String::from(name), mnemonics }) } else { Err(errors) }
+}
+
+// Reads the profile file at path, named after the file
+pub fn load_profile(path : &Path, extra : &[InstrSpec]) -> Result<Profile, Vec<AssembleError>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| vec![AssembleError::global(ErrorKind::CantRead(path.display().to_string(), e.to_string()))])?;
+    let name = path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned());
+    parse_profile(&name, &text, extra).map_err(|mut errors| {
+        for e in &mut errors { e.context.file = Some(path.display().to_string()); }
+        errors
+    })
+}
+
+// Takes the name of a float register without the '$', in any case: f0..f31
+pub fn parse_float_register(s : &str) -> Option<u32> {
+    let n = s.strip_prefix(['f', 'F'])?.parse::<u32>().ok()?;
+    if n < 32 { Some(n) } else { None }
+}
+
+// Canonical name of each register, by number
+pub const REGISTER_NAMES : [&str; 32] = [
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3",
+    "t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7",
+    "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7",
+    "t8", "t9", "k0", "k1", "gp", "sp", "fp", "ra",
+];
+
+// Takes the name without the '$', in any case: zero..ra, s8 (same as fp) or 0..31
+pub fn parse_register(s : &str) -> Option<u32> {
+    let s = s.to_lowercase();
+    if let Ok(n) = s.parse::<u32>() {
+        return if n < 32 { Some(n) } else { None };
+    }
+    if s == "s8" { return Some(30); }
+    REGISTER_NAMES.iter().position(|&name| name == s).map(|n| n as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Options;
+
+    // The first word of a program made of just this line
+    fn word(line : &str, opts : &Options) -> u32 {
+        crate::assemble(&format!("main: {}\n", line), opts).unwrap().text[0]
+    }
+
+    #[test]
+    fn encodes_known_words() {
+        let opts = Options::default();
+        for (line, expected) in [
+            ("add $t0, $t1, $t2", 0x012A_4020),
+            ("sub $t0, $t1, $t2", 0x012A_4022),
+            ("sll $t0, $t1, 4", 0x0009_4100),
+            ("lw $t0, 8($sp)", 0x8FA8_0008),
+            ("beq $t1, $t2, main", 0x1149_FFFF),
+            ("jal main", 0x0C00_0000),
+        ] {
+            assert_eq!(word(line, &opts), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn encodes_the_other_r_type_instructions() {
+        let opts = Options::default();
+        for (line, expected) in [
+            ("nor $t0, $t1, $t2", 0x012A_4027),
+            ("sra $t0, $t1, 4", 0x0009_4103),
+            ("srav $t0, $t1, $t2", 0x0149_4007),
+            ("jalr $t0, $t1", 0x0120_4009),
+            ("jalr $t1", 0x0120_F809),
+        ] {
+            assert_eq!(word(line, &opts), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn encodes_the_other_i_type_instructions() {
+        let opts = Options::default();
+        for (line, expected) in [
+            ("lui $t0, 0xFFFF", 0x3C08_FFFF),
+            ("andi $t0, $t1, 0xFFFF", 0x3128_FFFF),
+            ("xori $t0, $t1, 1", 0x3928_0001),
+            ("sltiu $t0, $t1, -1", 0x2D28_FFFF),
+        ] {
+            assert_eq!(word(line, &opts), expected, "{}", line);
+        }
+        let errors = crate::assemble("main: lui $t0, -1
+", &opts).unwrap_err();
+        assert_eq!(errors[0].kind, ErrorKind::OutOfRange(-1, 0, 0xFFFF));
+    }
+
+    #[test]
+    fn encodes_multiply_and_divide() {
+        let opts = Options::default();
+        for (line, expected) in [
+            ("mult $t1, $t2", 0x012A_0018),
+            ("divu $t1, $t2", 0x012A_001B),
+            ("mfhi $t0", 0x0000_4010),
+            ("mtlo $t0", 0x0100_0013),
+            ("madd $t1, $t2", 0x712A_0000),
+            ("mul $t0, $t1, $t2", 0x712A_4002),
+        ] {
+            assert_eq!(word(line, &opts), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn encodes_comparisons_with_zero() {
+        let opts = Options::default();
+        for (line, expected) in [
+            ("bltz $t0, main", 0x0500_FFFF),
+            ("bgez $t0, main", 0x0501_FFFF),
+            ("bltzal $t0, main", 0x0510_FFFF),
+            ("bgezal $t0, main", 0x0511_FFFF),
+            ("blez $t0, main", 0x1900_FFFF),
+            ("bgtz $t0, main", 0x1D00_FFFF),
+        ] {
+            assert_eq!(word(line, &opts), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn checks_immediates() {
+        let errors = crate::assemble("main: addi $t0, $t0, 0x8000\n", &Options::default()).unwrap_err();
+        assert_eq!(errors[0].kind, ErrorKind::OutOfRange(0x8000, -0x8000, 0x7FFF));
+        let errors = crate::assemble("main: ori $t0, $t0, -1\n", &Options::default()).unwrap_err();
+        assert_eq!(errors[0].kind, ErrorKind::OutOfRange(-1, 0, 0xFFFF));
+    }
+
+    #[test]
+    fn reads_instruction_set_files() {
+        let instructions = parse("# extra\nhalt format=J opcode=0x3F\nled format=I opcode=0x3E operands=rs,imm imm=unsigned\n").unwrap();
+        assert_eq!(instructions.len(), 2);
+        let opts = Options { instructions, ..Options::default() };
+        assert_eq!(word("halt", &opts), 0xFC00_0000);
+        assert_eq!(word("led $t0, 0xFFFF", &opts), 0xF900_FFFF);
+
+        let errors = parse("halt format=Q opcode=0x3F\nled opcode=1\n").unwrap_err();
+        assert_eq!(errors[0].kind, ErrorKind::BadIsaValue(String::from("Q"), String::from("format")));
+        assert_eq!(errors[1].kind, ErrorKind::MissingIsaField(String::from("format")));
+    }
+}
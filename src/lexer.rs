@@ -0,0 +1,332 @@
+// Turns the source text into typed tokens, each one carrying where it came from
+// so the passes (and error messages) can point back at the exact spot in the file.
+
+use crate::error::{AssembleError, ErrorKind};
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Default)]
+pub struct Span {
+    pub line : u32,    // 1-based line number
+    pub col : u32,     // 1-based column, counted in chars (a tab is one column)
+    pub start : usize, // Byte offset of the first char in the source
+    pub end : usize,   // Byte offset one past the last char
+    pub expansion : u32, // Macro expansion the token came out of, 0 if it was written as is
+}
+
+impl Span {
+    // From the start of the first token to the end of the last one
+    pub fn covering(tokens : &[Token]) -> Span {
+        Span { end: tokens[tokens.len() - 1].span.end, ..tokens[0].span }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum TokenKind {
+    Mnemonic(String),   // First word of a statement (after any labels), e.g. add
+    Register(String),   // Without the '$', e.g. t0
+    Integer(i64),
+    Float(f64),         // Only for .float and .double, e.g. 1.5 or 2.5e-3
+    Identifier(String), // Labels, both where they are defined and where they are used
+    Directive(String),  // Without the '.', e.g. word
+    Str(String),
+    Comma, Colon, LParen, RParen,
+    // Expression operators
+    Plus, Minus, Star, Slash, Percent, Shl, Shr, Amp, Pipe, Caret, Tilde,
+    EqEq, NotEq, Lt, Le, Gt, Ge, AndAnd, OrOr, Bang,
+    Newline,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Token {
+    pub kind : TokenKind,
+    pub span : Span,
+}
+
+struct Lexer<'a, 'e> {
+    src : &'a str,
+    pos : usize,
+    line : u32,
+    col : u32,
+    stmt_start : bool, // The next word is in mnemonic position
+    after_comma : bool, // A line ending in a comma continues on the next one
+    errors : &'e mut Vec<AssembleError>,
+}
+
+// Bad input is reported to errors and skipped, so lexing always reaches the end of the file
+pub fn tokenize(src : &str, errors : &mut Vec<AssembleError>) -> Vec<Token> {
+    let mut lexer = Lexer { src, pos: 0, line: 1, col: 1, stmt_start: true, after_comma: false, errors };
+    if src.starts_with('\u{FEFF}') { lexer.pos = '\u{FEFF}'.len_utf8(); }
+    let mut tokens : Vec<Token> = Vec::new();
+    while let Some(tok) = lexer.next_token() {
+        tokens.push(tok);
+    }
+    tokens
+}
+
+// Decimal, 0x hexadecimal, 0b binary or 0o octal (prefixes in any case), with optional _ separators
+pub fn parse_integer(text : &str) -> Option<i64> {
+    let digits : String = text.chars().filter(|&c| c != '_').collect();
+    let (radix, digits) = match digits.get(..2).map(|p| p.to_ascii_lowercase()).as_deref() {
+        Some("0x") => (16, &digits[2..]),
+        Some("0b") => (2, &digits[2..]),
+        Some("0o") => (8, &digits[2..]),
+        _ => (10, &digits[..]),
+    };
+    // from_str_radix would take a sign, but that's the lexer's job
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) { return None; }
+    i64::from_str_radix(digits, radix).ok()
+}
+
+// Decimal with a fraction and/or an exponent, like 1.5, 1e6 or 2.5e-3, with optional _ separators
+fn parse_float(text : &str) -> Option<f64> {
+    let digits : String = text.chars().filter(|&c| c != '_').collect();
+    if !digits.contains(['.', 'e', 'E']) || !digits.chars().all(|c| c.is_ascii_digit() || ".eE+-".contains(c)) { return None; }
+    digits.parse::<f64>().ok()
+}
+
+fn is_ident_start(c : char) -> bool { c.is_ascii_alphabetic() || c == '_' }
+fn is_ident_char(c : char) -> bool { c.is_ascii_alphanumeric() || c == '_' || c == '.' }
+
+impl Lexer<'_, '_> {
+    fn peek(&self) -> Option<char> { self.src[self.pos..].chars().next() }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        if c == '\n' { self.line += 1; self.col = 1; }
+        else { self.col += 1; }
+        Some(c)
+    }
+
+    fn eat_while(&mut self, f : impl Fn(char) -> bool) -> &str {
+        let start = self.pos;
+        while self.peek().is_some_and(&f) { self.bump(); }
+        &self.src[start..self.pos]
+    }
+
+    fn error(&mut self, kind : ErrorKind, line : u32, col : u32, start : usize) {
+        let span = Span { line, col, start, end: self.pos, expansion: 0 };
+        self.errors.push(AssembleError::new(kind, span, self.src));
+    }
+
+    // The char after a '\\' in strings and character literals, unknown ones are reported and become '\0'
+    fn escape(&mut self) -> char {
+        let (line, col, start) = (self.line, self.col - 1, self.pos - 1);
+        match self.bump() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some(e @ ('\\' | '"' | '\'')) => e,
+            _ => {
+                self.error(ErrorKind::UnknownEscape, line, col, start);
+                '\0'
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Option<Token> {
+        loop {
+            // Skip whitespace (a stray '\r' from CRLF line endings counts as whitespace) and comments
+            match self.peek()? {
+                ' ' | '\t' | '\r' => { self.bump(); continue; }
+                '\n' if self.after_comma => { self.bump(); continue; }
+                '#' => { self.eat_while(|c| c != '\n'); continue; }
+                _ => (),
+            }
+            let (line, col, start) = (self.line, self.col, self.pos);
+            let c = self.bump()?;
+            let Some(kind) = self.lex_kind(c, line, col, start) else { continue };
+            // Labels being defined keep the statement start going, anything else ends it
+            if !matches!(kind, TokenKind::Newline | TokenKind::Colon | TokenKind::Identifier(_)) {
+                self.stmt_start = false;
+            }
+            self.after_comma = kind == TokenKind::Comma;
+            return Some(Token { kind, span: Span { line, col, start, end: self.pos, expansion: 0 } });
+        }
+    }
+
+    // None means the char was reported as an error and skipped
+    fn lex_kind(&mut self, c : char, line : u32, col : u32, start : usize) -> Option<TokenKind> {
+        Some(match c {
+            '\n' => { self.stmt_start = true; TokenKind::Newline }
+            ',' => TokenKind::Comma,
+            ':' => { self.stmt_start = true; TokenKind::Colon }
+            '(' => TokenKind::LParen,
+            ')' => TokenKind::RParen,
+            '+' => TokenKind::Plus,
+            '-' => TokenKind::Minus,
+            '*' => TokenKind::Star,
+            '/' => TokenKind::Slash,
+            '%' => TokenKind::Percent,
+            '&' if self.peek() == Some('&') => { self.bump(); TokenKind::AndAnd }
+            '&' => TokenKind::Amp,
+            '|' if self.peek() == Some('|') => { self.bump(); TokenKind::OrOr }
+            '|' => TokenKind::Pipe,
+            '^' => TokenKind::Caret,
+            '~' => TokenKind::Tilde,
+            '<' if self.peek() == Some('<') => { self.bump(); TokenKind::Shl }
+            '<' if self.peek() == Some('=') => { self.bump(); TokenKind::Le }
+            '<' => TokenKind::Lt,
+            '>' if self.peek() == Some('>') => { self.bump(); TokenKind::Shr }
+            '>' if self.peek() == Some('=') => { self.bump(); TokenKind::Ge }
+            '>' => TokenKind::Gt,
+            '=' if self.peek() == Some('=') => { self.bump(); TokenKind::EqEq }
+            '!' if self.peek() == Some('=') => { self.bump(); TokenKind::NotEq }
+            '!' => TokenKind::Bang,
+            '$' => {
+                let name = String::from(self.eat_while(|c| c.is_ascii_alphanumeric()));
+                if name.is_empty() {
+                    self.error(ErrorKind::MissingRegisterName, line, col, start);
+                    return None;
+                }
+                TokenKind::Register(name)
+            }
+            '.' => {
+                let name = String::from(self.eat_while(is_ident_char));
+                if name.is_empty() {
+                    self.error(ErrorKind::MissingDirectiveName, line, col, start);
+                    return None;
+                }
+                TokenKind::Directive(name)
+            }
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    if matches!(self.peek(), Some('\n') | None) {
+                        self.error(ErrorKind::UnterminatedString, line, col, start);
+                        break;
+                    }
+                    match self.bump() {
+                        Some('"') => break,
+                        Some('\\') => s.push(self.escape()),
+                        Some(c) => s.push(c),
+                        None => break,
+                    }
+                }
+                TokenKind::Str(s)
+            }
+            // A character stands for its code, e.g. 'A' is 65
+            '\'' => {
+                let value = match self.bump() {
+                    Some('\\') => Some(self.escape()),
+                    Some('\'' | '\n') | None => None,
+                    Some(c) => Some(c),
+                };
+                match (value, self.peek()) {
+                    (Some(c), Some('\'')) => {
+                        self.bump();
+                        TokenKind::Integer(c as i64)
+                    }
+                    _ => {
+                        // Skip to the closing quote (if any) so the rest of the line still makes sense
+                        self.eat_while(|c| c != '\'' && c != '\n');
+                        if self.peek() == Some('\'') { self.bump(); }
+                        self.error(ErrorKind::BadCharacter, line, col, start);
+                        TokenKind::Integer(0)
+                    }
+                }
+            }
+            '0'..='9' => {
+                self.eat_while(|c| c.is_ascii_alphanumeric() || c == '_');
+                let is_digit = |s : &str| s.starts_with(|c : char| c.is_ascii_digit());
+                // A fraction after a decimal number makes it a float, and so does an exponent
+                if self.src[start..self.pos].chars().all(|c| c.is_ascii_digit() || c == '_')
+                    && self.peek() == Some('.') && is_digit(&self.src[self.pos + 1..]) {
+                    self.bump();
+                    self.eat_while(|c| c.is_ascii_alphanumeric() || c == '_');
+                }
+                let mantissa = self.src[start..self.pos].strip_suffix(['e', 'E']);
+                if mantissa.is_some_and(|m| m.chars().all(|c| c.is_ascii_digit() || c == '_' || c == '.'))
+                    && matches!(self.peek(), Some('+' | '-')) && is_digit(&self.src[self.pos + 1..]) {
+                    self.bump();
+                    self.eat_while(|c| c.is_ascii_digit() || c == '_');
+                }
+                let text = &self.src[start..self.pos];
+                match (parse_integer(text), parse_float(text)) {
+                    (Some(v), _) => TokenKind::Integer(v),
+                    (None, Some(v)) => TokenKind::Float(v),
+                    _ => {
+                        let text = String::from(text);
+                        self.error(ErrorKind::InvalidNumber(text), line, col, start);
+                        TokenKind::Integer(0)
+                    }
+                }
+            }
+            c if is_ident_start(c) => {
+                self.eat_while(is_ident_char);
+                let name = String::from(&self.src[start..self.pos]);
+                // A word at the start of a statement is a mnemonic, unless it's a label being defined
+                let is_label = self.src[self.pos..].trim_start_matches([' ', '\t']).starts_with(':');
+                if self.stmt_start && !is_label { TokenKind::Mnemonic(name) }
+                else { TokenKind::Identifier(name) }
+            }
+            _ => {
+                self.error(ErrorKind::UnexpectedChar(c), line, col, start);
+                return None;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(src : &str) -> Vec<TokenKind> {
+        let mut errors : Vec<AssembleError> = Vec::new();
+        let tokens = tokenize(src, &mut errors);
+        assert!(errors.is_empty(), "{:?}", errors);
+        tokens.into_iter().map(|t| t.kind).filter(|k| *k != TokenKind::Newline).collect()
+    }
+
+    #[test]
+    fn parses_integers() {
+        assert_eq!(parse_integer("42"), Some(42));
+        assert_eq!(parse_integer("0x1F"), Some(31));
+        assert_eq!(parse_integer("0X1f"), Some(31));
+        assert_eq!(parse_integer("0b1010"), Some(10));
+        assert_eq!(parse_integer("0o17"), Some(15));
+        assert_eq!(parse_integer("0x1234_5678"), Some(0x1234_5678));
+        assert_eq!(parse_integer("12a"), None);
+    }
+
+    #[test]
+    fn tokenizes_a_statement() {
+        assert_eq!(kinds("loop: addi $t0, x, 'A' # comment"), vec![
+            TokenKind::Identifier(String::from("loop")), TokenKind::Colon,
+            TokenKind::Mnemonic(String::from("addi")), TokenKind::Register(String::from("t0")), TokenKind::Comma,
+            TokenKind::Identifier(String::from("x")), TokenKind::Comma, TokenKind::Integer(65),
+        ]);
+        assert_eq!(kinds(".double 2.5e-3"), vec![TokenKind::Directive(String::from("double")), TokenKind::Float(2.5e-3)]);
+    }
+
+    #[test]
+    fn tracks_spans() {
+        let tokens = tokenize("nop\n  add $t0", &mut Vec::new());
+        let add = tokens.iter().find(|t| t.kind == TokenKind::Mnemonic(String::from("add"))).unwrap();
+        assert_eq!(add.span, Span { line: 2, col: 3, start: 6, end: 9, expansion: 0 });
+    }
+
+    #[test]
+    fn takes_crlf_bom_and_tabs() {
+        let tokens = tokenize("\u{FEFF}nop\r\n\tadd\t$t0,\t$t1\r\n", &mut Vec::new());
+        assert_eq!(tokens[0].kind, TokenKind::Mnemonic(String::from("nop")));
+        assert_eq!((tokens[0].span.line, tokens[0].span.col, tokens[0].span.start), (1, 1, 3));
+        // A tab is one column
+        let add = &tokens[2];
+        assert_eq!(add.kind, TokenKind::Mnemonic(String::from("add")));
+        assert_eq!((add.span.line, add.span.col), (2, 2));
+        assert_eq!(kinds("nop\r\n\tadd\t$t0,\t$t1\r\n"), vec![
+            TokenKind::Mnemonic(String::from("nop")), TokenKind::Mnemonic(String::from("add")),
+            TokenKind::Register(String::from("t0")), TokenKind::Comma, TokenKind::Register(String::from("t1")),
+        ]);
+    }
+
+    #[test]
+    fn reports_bad_input() {
+        let mut errors : Vec<AssembleError> = Vec::new();
+        tokenize("add @ 0x", &mut errors);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].kind, ErrorKind::UnexpectedChar('@'));
+    }
+}
@@ -0,0 +1,127 @@
+// quamgears as a library: assemble() a source into a Program, and mif renders it for Quartus
+
+mod assembler;
+pub mod error;
+pub mod expr;
+pub mod instruction;
+pub mod isa;
+pub mod lexer;
+pub mod mif;
+mod preprocess;
+pub mod pseudo;
+mod symbols;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use isa::{InstrSpec, Profile};
+
+pub use assembler::{assemble, assemble_file};
+pub use error::{AssembleError, ErrorKind};
+
+// Which end of a data word its first byte goes in
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum Endian { Big, Little }
+
+// What an address counts: words, like the datapath this was first made for, or bytes like real MIPS
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum Addressing { Word, Byte }
+
+// The addresses labels get, which is what ends up in branches, jumps, loads and .word data.
+// Every address here is counted in the unit addressing says
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct AddressModel {
+    pub addressing : Addressing,
+    pub text_base : u32, // Address of the first instruction
+    pub data_base : u32, // Address of the first data word
+    pub gp : Option<u32>, // Value of $gp, to load and store data labels as offsets from it instead of from $zero
+}
+
+impl Default for AddressModel {
+    fn default() -> AddressModel { AddressModel::WORD }
+}
+
+impl AddressModel {
+    // Everything starts at 0, with jump labels being word indexes and data labels word offsets
+    pub const WORD : AddressModel = AddressModel { addressing: Addressing::Word, text_base: 0, data_base: 0, gp: None };
+    pub const BYTE : AddressModel = AddressModel { addressing: Addressing::Byte, text_base: 0, data_base: 0, gp: None };
+    // Where MARS puts .text and .data
+    pub const MARS : AddressModel = AddressModel { addressing: Addressing::Byte, text_base: 0x0040_0000, data_base: 0x1001_0000, gp: None };
+
+    pub fn named(name : &str) -> Option<AddressModel> {
+        match name {
+            "word" => Some(AddressModel::WORD),
+            "byte" => Some(AddressModel::BYTE),
+            "mars" => Some(AddressModel::MARS),
+            _ => None,
+        }
+    }
+
+    // Word addresses are worth 4 bytes each, byte addresses are already bytes
+    pub fn bytes(&self, address : i64) -> i64 {
+        match self.addressing {
+            Addressing::Word => address * 4,
+            Addressing::Byte => address,
+        }
+    }
+
+    // Address of the instruction at word index of the program memory
+    pub fn text_address(&self, index : u32) -> i64 {
+        match self.addressing {
+            Addressing::Word => self.text_base as i64 + index as i64,
+            Addressing::Byte => self.text_base as i64 + index as i64 * 4,
+        }
+    }
+
+    // Address of the data at a byte offset into the data memory, which is the word holding it with word addressing
+    pub fn data_address(&self, offset : u32) -> i64 {
+        match self.addressing {
+            Addressing::Word => self.data_base as i64 + (offset >> 2) as i64,
+            Addressing::Byte => self.data_base as i64 + offset as i64,
+        }
+    }
+
+    // Byte and halfword loads and stores always need the address of the byte itself
+    pub fn data_byte_address(&self, offset : u32) -> i64 {
+        self.bytes(self.data_base as i64) + offset as i64
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Options {
+    pub depth : u32, // Words in each memory, both for instructions and data
+    pub strict : bool, // Only take lowercase mnemonics and $name registers, for course submissions
+    pub entry : Option<String>, // Label where the program starts running, main (if there is one) when None
+    pub include_paths : Vec<PathBuf>, // Where .include looks after the including file's own directory
+    pub defines : Vec<(String, i64)>, // Constants defined before the first line, like -D NAME=value
+    pub endian : Endian, // How .half and .byte data is packed into words
+    pub exception_vector : u32, // Word where the .ktext code goes
+    pub address_model : AddressModel,
+    pub instructions : Vec<InstrSpec>, // On top of the built-in ones, like the ones read by isa::load
+    pub profile : Option<Profile>, // The only instructions allowed, None for all of them
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options { depth: 256, strict: false, entry: None, include_paths: Vec::new(), defines: Vec::new(),
+            endian: Endian::Big, exception_vector: 0x60, address_model: AddressModel::WORD,
+            instructions: Vec::new(), profile: None }
+    }
+}
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum SymbolKind { Jump, Data }
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct Symbol {
+    pub kind : SymbolKind,
+    pub address : u32, // What the label is worth in the address model, like in expressions
+    pub offset : u32, // Where it is in the memory: word index in program.text for jump labels, byte offset for data labels
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Program {
+    pub text : Vec<u32>, // Encoded instructions, one per word starting at 0
+    pub data : Vec<u32>, // Data memory contents, one per word starting at 0
+    pub symbols : HashMap<String, Symbol>,
+    pub includes : Vec<PathBuf>, // Every file read by .include, once each
+}
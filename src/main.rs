@@ -1,498 +1,229 @@
-use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use quamgears::{isa, lexer, mif, AddressModel, Endian, Options};
 
-// Used for program.mif and dmemory.mif
-// PROGram RAM Memory Initialization File
-// Data MEMORY Memory Initialization File
-const TOP_PROGRAM_COMMENT : &str = "MIPS Instruction Memory Initialization File";
-const TOP_DATA_COMMENT : &str = "MIPS Data Memory Initialization File";
-const DEPTH : u32 = 256;
-const WIDTH : u32 = 32;
-const ADDRESS_RADIX : &str = "HEX";
-const DATA_RADIX : &str = "HEX";
+// Make reads $ as a variable, # as a comment and spaces as the end of a name
+fn make_escape(path : &Path) -> String {
+    path.display().to_string().replace('$', "$$").replace('#', "\\#").replace(' ', "\\ ")
+}
 
-#[derive(Copy,Clone,PartialEq)]
-enum Section { Global, Text, Data }
+// NAME=value or just NAME (which is 1), the value being written like in the source
+fn parse_define(arg : &str) -> Option<(String, i64)> {
+    let (name, value) = arg.split_once('=').unwrap_or((arg, "1"));
+    let value : i64 = match value.strip_prefix('-') {
+        Some(digits) => -lexer::parse_integer(digits)?,
+        None => lexer::parse_integer(value)?,
+    };
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if valid { Some((String::from(name), value)) } else { None }
+}
 
-#[derive(Debug,Copy,Clone,PartialEq)]
-enum Token {
-    // Registers
-    Zero, AT, V0, V1, A0, A1, A2, A3, T0, T1, T2,
-    T3, T4, T5, T6, T7, S0, S1, S2, S3, S4,
-    S5, S6, S7, T8, T9, K0, K1, GP, SP, FP, RA,
-    // Instructions
-    SW, LW, ADDI, BEQ, BNE, AND, OR, ADD, SUB, SLT,
-    SRL, SLL, JR, J, JAL,
-    // Immediate values
-    I,
-    // Directives
-    Global, Text, Data, Word, Space,
-    // Jump and Data Labels
-    Label,
-    // Can't find
-    NotFound,
+// An address given after flag, which exits when it's missing or isn't a 32 bit number
+fn parse_address(flag : &str, value : Option<&String>) -> u32 {
+    match value.map(|address| (address, lexer::parse_integer(address))) {
+        Some((_, Some(address))) if (0..=u32::MAX as i64).contains(&address) => address as u32,
+        Some((address, _)) => {
+            eprintln!("ERROR: {} is not a valid address for {}!", address, flag);
+            std::process::exit(1);
+        }
+        None => {
+            eprintln!("ERROR: {} needs an address!", flag);
+            std::process::exit(1);
+        }
+    }
 }
 
 fn main(){
     let args : Vec<String> = std::env::args().collect();
-
-    if args.len() <= 1 {
-        eprintln!("ERROR: Can't execute without arguments!");
-        std::process::exit(1);
-    }
-    else if args.len() >= 3 {
-        eprintln!("ERROR: Too many arguments!");
-        std::process::exit(1);
-    }
-
-    let arg1_ext = args[1][args[1].len()-4..args[1].len()].to_lowercase();
-
-    if arg1_ext != ".asm" {
-        eprintln!("ERROR: Use an .asm mips assembly file as an argument!");
-        std::process::exit(1);
-    }
-
-    let fdata = std::fs::read_to_string(&args[1]).expect("ERROR: Can't read file or file doesn't exist!");
-
-    let mut fvec : Vec<&str> = fdata.split("\n").collect();
-    fvec.retain(|&x| x.len() > 3);
-
-    // Begin program.mif
-    println!("-- {}", TOP_PROGRAM_COMMENT);
-    println!("Depth = {};", DEPTH);
-    println!("Width = {};", WIDTH);
-    println!("Address_radix = {};", ADDRESS_RADIX);
-    println!("Data_radix = {};", DATA_RADIX);
-    println!("Content");
-    println!("Begin");
-
-    // .globl means outside either .text or .data, .text means the program instructions/code, .data means program data
-    let mut section : Section = Section::Global;
-    let mut word_count : u32 = 0; // Used for jump labels
-    let mut mem_byte_alignment : u32 = 0; // Used for data labels
-    let mut jump_labels : HashMap<String, u32> = HashMap::new();
-    let mut data_labels : HashMap<String, u32> = HashMap::new();
-    // Get all Jump and Data labels
-    for (i, line) in fvec.iter().enumerate() {
-        let no_com_line : &str = &line[..line.find('#').unwrap_or(line.len())]; // Strip comments
-        if no_com_line.trim().is_empty() { continue; } // Skip empty lines
-        let mut no_com_line_it = no_com_line.trim().split_whitespace();
-        let str_tok : String = String::from(no_com_line_it.next().unwrap());
-        let enum_tok = parse_first_token(&str_tok);
-        match enum_tok {
-            Token::Text => {
-                section = Section::Text;
-                word_count = 0;
-            }
-            Token::Data => {
-                section = Section::Data;
-                word_count = 0;
-                mem_byte_alignment = 0;
-            }
-            Token::Label => match section {
-                Section::Global => panic!("ERROR({}): There shouldn't be any free labels at the global section!", i+1),
-                Section::Text => {
-                    let label : &str = &str_tok[..str_tok.len()-1];
-                    if data_labels.contains_key(label) {
-                        panic!("ERROR({}): The label {} was defined twice or more times!", i+1, label);
-                    }
-                    jump_labels.insert(String::from(label), word_count);
+    let mut opts = Options::default();
+    let mut file : Option<&String> = None;
+    let mut depfile : Option<&String> = None;
+    let mut depfile_target : Option<&String> = None;
+    let mut isa_files : Vec<&Path> = Vec::new();
+    let mut profile : Option<&String> = None;
+    // The bases and $gp go on top of the model, whichever order they are given in
+    let (mut text_base, mut data_base, mut gp) : (Option<u32>, Option<u32>, Option<u32>) = (None, None, None);
+
+    let mut args_it = args[1..].iter();
+    while let Some(arg) = args_it.next() {
+        match arg.as_str() {
+            "--strict" => opts.strict = true,
+            "--entry" => match args_it.next() {
+                Some(entry) => opts.entry = Some(entry.clone()),
+                None => {
+                    eprintln!("ERROR: --entry needs a label name!");
+                    std::process::exit(1);
                 }
-                Section::Data => {
-                    let data_sz : u32 = match no_com_line_it.next().unwrap() {
-                        ".word" | ".space" => 4,
-                        ".byte" => panic!("ERROR({}): Use .word instead of .byte because of memory alignment.", i+1),
-                        ".float" | ".double" => panic!("ERROR({}): There are no float-altering instructions implemented, so you can't use .float", i+1),
-                        _ => panic!("ERROR({}): {} is not implemented!", i+1, str_tok),
-                    };
-                    let label : &str = &str_tok[..str_tok.len()-1];
-                    if jump_labels.contains_key(label) { panic!("ERROR({}): The label {} was defined twice or more times!", i+1, label); }
-                    // Using byte memory alignment here instead of word alignment for maybe future use
-                    data_labels.insert(String::from(label), mem_byte_alignment);
-                    for _ in no_com_line_it { mem_byte_alignment += data_sz; }
+            },
+            "--endian" => match args_it.next().map(String::as_str) {
+                Some("big") => opts.endian = Endian::Big,
+                Some("little") => opts.endian = Endian::Little,
+                _ => {
+                    eprintln!("ERROR: --endian should be followed by big or little!");
+                    std::process::exit(1);
                 }
-            }
-            _ => (),
-        }
-        match section {
-            // NOTE: This leaves a lot to be desired, and requires strict syntax enforcing
-            Section::Text => if enum_tok != Token::Text && enum_tok != Token::Label { word_count += 1; },
-            Section::Data => if enum_tok != Token::Data { word_count += 1; },
-            _ => (),
-        }
-        //println!("{{(Str_Token)={} (Enum_Token)={:?}}} ", str_tok, enum_tok);
-    }
-    // NOTE: This is done because I'm lazy
-    if jump_labels["main"] != 0 {
-        eprintln!("ERROR: The main label should always be the starting one before any code.");
-        std::process::exit(1);
-    }
-
-    // Generate each instruction
-    for (i, line) in fvec.iter().enumerate() {
-        let no_com_line : &str = &line[..line.find('#').unwrap_or(line.len())]; // Strip comments
-        if no_com_line.trim().is_empty() { continue; } // Skip empty lines
-        let mut args : HashMap<String, Option<u32>> = HashMap::new(); // This will hold the instruction's arguments
-        let mut no_com_line_it = no_com_line.trim().split_whitespace(); // no comments line iterator
-        let first_str_tok : String = String::from(no_com_line_it.next().unwrap());
-        let first_enum_tok : Token = parse_first_token(&first_str_tok);
-        if first_enum_tok == Token::Text {
-            section = Section::Text;
-            word_count = 0;
-            continue;
-        }
-        else if section == Section::Text && first_enum_tok != Token::Label { word_count += 1; }
-        else if section != Section::Text { continue; }
-        for word in no_com_line_it {
-            let str_tok : String = String::from(word.replace(&[','][..], ""));
-            let enum_tok = parse_token(&str_tok);
-            match first_enum_tok {
-                //// Instructions
-                //SW, LW, ADDI, BEQ, BNE, AND, OR, ADD, SUB, SLT,
-                //SRL, SLL, JR, J, JAL,
-
-                // I-Format Instructions
-                Token::SW | Token::LW | Token::ADDI | Token::BEQ | Token::BNE => {
-                    args.insert(String::from("opcode"), Some(
-                            match first_enum_tok {
-                                Token::SW => 0x2B,
-                                Token::LW => 0x23,
-                                Token::ADDI => 0x08,
-                                Token::BEQ => 0x04,
-                                Token::BNE => 0x05,
-                                // TODO: check if this condition ever reaches
-                                _ => panic!("ERROR({}): Instruction {} is not implemented.", i+1, first_str_tok),
-                            }
-                            << 26));
-                    if (enum_tok as u32) < 32 {
-                        if args.contains_key("rt") { args.insert(String::from("rs"), Some((enum_tok as u32) << 21)); }
-                        else { args.insert(String::from("rt"), Some((enum_tok as u32) << 16)); }
-                    }
-                    else if enum_tok == Token::I {
-                        if str_tok.starts_with("0x") && u32::from_str_radix(&str_tok[2..], 16).is_ok() {
-                            args.insert(String::from("i"), Some(u32::from_str_radix(&str_tok[2..], 16).unwrap()));
-                        }
-                        else {
-                            args.insert(String::from("i"), Some(str_tok.parse::<u32>().unwrap()));
-                        }
-                    }
-                    else if enum_tok == Token::NotFound {
-                        if data_labels.contains_key(&str_tok) {
-                            args.insert(String::from("i"), Some(data_labels[&str_tok] >> 2)); // Divide by 4 since it's word aligned
-                        }
-                        else if jump_labels.contains_key(&str_tok) {
-                            args.insert(String::from("i"), Some((jump_labels[&str_tok] as i32 - word_count as i32) as u32 & 0xFFFF));
-                        }
-                        else if str_tok.contains("(") && str_tok.contains(")"){
-                            let offset : u32 =
-                                if str_tok.starts_with("0x") && u32::from_str_radix(&str_tok[2..str_tok.find('(').unwrap()], 16).is_ok() {
-                                    u32::from_str_radix(&str_tok[2..str_tok.find('(').unwrap()], 16).unwrap()
-                                } else { str_tok[..str_tok.find('(').unwrap()].parse::<u32>().unwrap() };
-                            let label : &str = &str_tok[str_tok.find('(').unwrap() + 1..str_tok.find(')').unwrap()];
-                            if !data_labels.contains_key(&String::from(label)) {
-                                let reg_token : Token = parse_token(&String::from(label));
-                                if (reg_token as u32) < 32 {
-                                    args.insert(String::from("rs"), Some((reg_token as u32) << 21));
-                                }
-                                else {
-                                    panic!("ERROR({}): Label {} does not exist!", i+1, label);
-                                }
-                            }
-                            else {
-                                args.insert(String::from("i"), Some((data_labels[&String::from(label)] >> 2) + offset));
-                            }
-                        }
-                        else { panic!("ERROR({}): Label {} does not exist!", i+1, str_tok); }
-                    }
-                    else { panic!("ERROR({}): {} instruction has wrong syntax! This is wrong: {}", i+1,
-                        match first_enum_tok {
-                            Token::SW => "SW",
-                            Token::LW => "LW",
-                            Token::I => "I",
-                            Token::BEQ => "BEQ",
-                            Token::BNE => "BNE",
-                            // TODO: check if this condition ever reaches
-                            _ => "",
-                        }, str_tok); }
+            },
+            "--exception-vector" => opts.exception_vector = parse_address(arg, args_it.next()),
+            "--address-model" => match args_it.next().map(|name| AddressModel::named(name)) {
+                Some(Some(model)) => opts.address_model = model,
+                _ => {
+                    eprintln!("ERROR: --address-model should be followed by word, byte or mars!");
+                    std::process::exit(1);
                 }
-
-                // R-Format Instructions
-                Token::AND | Token::OR | Token::ADD | Token::SUB | Token::SLT | Token::SRL | Token::SLL | Token::JR => {
-                    args.insert(String::from("func"), Some(
-                            match first_enum_tok {
-                                Token::AND => 0x24,
-                                Token::OR => 0x25,
-                                Token::ADD => 0x20,
-                                Token::SUB => 0x22,
-                                Token::SLT => 0x2A,
-                                Token::SRL => 0x02,
-                                Token::SLL => 0x00,
-                                Token::JR => 0x08,
-                                // TODO: check if this condition ever reaches
-                                _ => panic!("ERROR({}): Instruction {} is not implemented.", i+1, first_str_tok),
-                            }));
-                    if (enum_tok as u32) < 32 {
-                        if args.contains_key("rs") ||
-                            (args.contains_key("rd") && (first_enum_tok == Token::SLL || first_enum_tok == Token::SRL)) {
-                            args.insert(String::from("rt"), Some((enum_tok as u32) << 16));
-                        }
-                        else if args.contains_key("rd") || first_enum_tok == Token::JR {
-                            args.insert(String::from("rs"), Some((enum_tok as u32) << 21));
-                        }
-                        else { args.insert(String::from("rd"), Some((enum_tok as u32) << 11)); }
-                    }
-                    else if enum_tok == Token::I {
-                        if str_tok.starts_with("0x") && u32::from_str_radix(&str_tok[2..], 16).is_ok() {
-                            args.insert(String::from("shamt"), Some(u32::from_str_radix(&str_tok[2..], 16).unwrap()));
-                        }
-                        else {
-                            args.insert(String::from("shamt"), Some(str_tok.parse::<u32>().unwrap() << 6));
-                        }
-                    }
-                    else { panic!("ERROR({}): {} instruction has wrong syntax! This is wrong: {}", i+1,
-                        match first_enum_tok {
-                            Token::AND => "AND",
-                            Token::OR => "OR",
-                            Token::ADD => "ADD",
-                            Token::SUB => "SUB",
-                            Token::SLT => "SLT",
-                            Token::SRL => "SRL",
-                            Token::SLL => "SLL",
-                            Token::JR => "JR",
-                            _ => "",
-                        }, str_tok); }
+            },
+            "--text-base" => text_base = Some(parse_address(arg, args_it.next())),
+            "--data-base" => data_base = Some(parse_address(arg, args_it.next())),
+            "--gp" => gp = Some(parse_address(arg, args_it.next())),
+            "-I" => match args_it.next() {
+                Some(dir) => opts.include_paths.push(PathBuf::from(dir)),
+                None => {
+                    eprintln!("ERROR: -I needs a directory!");
+                    std::process::exit(1);
                 }
-
-                // J-Format instructions
-                Token::J | Token::JAL => {
-                    args.insert(String::from("opcode"), Some(
-                            match first_enum_tok {
-                                Token::J => 0x02,
-                                Token::JAL => 0x03,
-                                // TODO: check if this condition ever reaches
-                                _ => panic!("ERROR({}): Instruction {} is not implemented.", i+1, first_str_tok),
-                            }
-                            << 26));
-                    if enum_tok == Token::NotFound {
-                        if jump_labels.contains_key(&str_tok) {
-                            args.insert(String::from("i"), Some(jump_labels[&str_tok] & 0x3FFFFFF));
-                        }
-                        else { panic!("ERROR({}): Label {} does not exist!", i+1, str_tok); }
-                    }
-                    else { panic!("ERROR({}): {} instruction has wrong syntax! This is wrong: {}", i+1,
-                        match first_enum_tok {
-                            Token::J => "J",
-                            Token::JAL => "JAL",
-                            // TODO: check if this condition ever reaches
-                            _ => "",
-                        }, str_tok); }
+            },
+            _ if arg.starts_with("-I") => opts.include_paths.push(PathBuf::from(&arg[2..])),
+            "-D" => match args_it.next().map(|define| (define, parse_define(define))) {
+                Some((_, Some(define))) => opts.defines.push(define),
+                Some((define, None)) => {
+                    eprintln!("ERROR: -D {} should be like -D NAME=value or -D NAME!", define);
+                    std::process::exit(1);
+                }
+                None => {
+                    eprintln!("ERROR: -D needs a constant like NAME=value!");
+                    std::process::exit(1);
+                }
+            },
+            _ if arg.starts_with("-D") => match parse_define(&arg[2..]) {
+                Some(define) => opts.defines.push(define),
+                None => {
+                    eprintln!("ERROR: {} should be like -DNAME=value or -DNAME!", arg);
+                    std::process::exit(1);
+                }
+            },
+            "--isa" => match args_it.next() {
+                Some(path) => isa_files.push(Path::new(path)),
+                None => {
+                    eprintln!("ERROR: --isa needs an instruction set file!");
+                    std::process::exit(1);
                 }
-                _ => { }
+            },
+            "--profile" => match args_it.next() {
+                Some(name) => profile = Some(name),
+                None => {
+                    eprintln!("ERROR: --profile needs a profile name or file!");
+                    std::process::exit(1);
+                }
+            },
+            "--depfile-target" => match args_it.next() {
+                Some(target) => depfile_target = Some(target),
+                None => {
+                    eprintln!("ERROR: --depfile-target needs a file name!");
+                    std::process::exit(1);
+                }
+            },
+            "--depfile" => match args_it.next() {
+                Some(path) => depfile = Some(path),
+                None => {
+                    eprintln!("ERROR: --depfile needs a file name!");
+                    std::process::exit(1);
+                }
+            },
+            _ if arg.starts_with('-') => {
+                eprintln!("ERROR: Unknown option {}!", arg);
+                std::process::exit(1);
             }
-        }
-        // Here we have all the tokens data and can finally make the instruction in hexadecimal
-        if !args.is_empty() {
-            //println!("Instruct: {:?} Args: {:?}", first_enum_tok, args);
-            println!(
-                "\t{:02X}: {:08X};", word_count - 1,
-                args.remove("opcode").unwrap_or(Some(0)).unwrap() |
-                args.remove("addr")  .unwrap_or(Some(0)).unwrap() | // J - Format only
-                args.remove("rs")    .unwrap_or(Some(0)).unwrap() |
-                args.remove("rt")    .unwrap_or(Some(0)).unwrap() |
-                args.remove("i")     .unwrap_or(Some(0)).unwrap() | // I - Format only
-                args.remove("rd")    .unwrap_or(Some(0)).unwrap() | // R - Format only
-                args.remove("shamt") .unwrap_or(Some(0)).unwrap() | // R - Format only
-                args.remove("func")  .unwrap_or(Some(0)).unwrap()   // R - Format only
-                )
+            _ if file.is_some() => {
+                eprintln!("ERROR: Too many arguments!");
+                std::process::exit(1);
+            }
+            _ => file = Some(arg),
         }
     }
-    if word_count - 1 < 0xFF { println!("\t[{:02X}..FF]: 00000000;", word_count - 1); }
-    else if word_count > DEPTH {
-        eprintln!("ERROR: Too many instructions! There can be at most {} instructions!", DEPTH);
-        std::process::exit(1);
-    }
 
-    // End program.mif
-    println!("End;");
+    opts.address_model.text_base = text_base.unwrap_or(opts.address_model.text_base);
+    opts.address_model.data_base = data_base.unwrap_or(opts.address_model.data_base);
+    opts.address_model.gp = gp.or(opts.address_model.gp);
 
-    // Begin dmemory.mif
-    println!("-- {}", TOP_DATA_COMMENT);
-    println!("Depth = {};", DEPTH);
-    println!("Width = {};", WIDTH);
-    println!("Address_radix = {};", ADDRESS_RADIX);
-    println!("Data_radix = {};", DATA_RADIX);
-    println!("Content");
-    println!("Begin");
+    let Some(file) = file else {
+        eprintln!("ERROR: Can't execute without arguments!");
+        std::process::exit(1);
+    };
 
-    for (i, line) in fvec.iter().enumerate() {
-        let no_com_line : &str = &line[..line.find('#').unwrap_or(line.len())]; // Strip comments
-        if no_com_line.trim().is_empty() { continue; } // Skip empty lines
-        let mut no_com_line_it = no_com_line.trim().split_whitespace(); // no comments line iterator
-        let first_str_tok : String = String::from(no_com_line_it.next().unwrap());
-        let first_enum_tok : Token = parse_first_token(&first_str_tok);
-        if first_enum_tok == Token::Data {
-            section = Section::Data;
-            word_count = 0;
-            continue;
-        }
-        else if section == Section::Data && first_enum_tok != Token::Label {
-            panic!("ERROR({}): Make sure data labels are inline with the data itself!", i+1);
-        }
-        else if section != Section::Data { continue; }
-        let second_str_tok : String = String::from(no_com_line_it.next().unwrap());
-        let second_enum_tok : Token = parse_token(&second_str_tok);
-        match second_enum_tok {
-            Token::Word | Token::Space => (),
-            _ => panic!("ERROR({}): Unsupported directive {}! Only .word and .space are implemented.", i+1, second_str_tok),
-        }
-        if second_enum_tok == Token::Space {
-            println!("\t{:02X} : 00000000;", word_count);
-            word_count += 1;
-            continue;
-        }
-        for word in no_com_line_it {
-            print!("\t{:02X} : ", word_count);
-            word_count += 1;
-            let val_str_tok : String = String::from(word.replace(&[','][..], ""));
-            let val_enum_tok : Token = parse_token(&val_str_tok);
-            if val_enum_tok != Token::I {
-                panic!("ERROR({}): Unsupported token {}! Only immediate values can come after .word or .space", i+1, val_str_tok);
-            }
-            if val_str_tok.starts_with("0x") && u32::from_str_radix(&val_str_tok[2..], 16).is_ok() {
-                println!("{:08X};", u32::from_str_radix(&val_str_tok[2..], 16).unwrap());
-            }
-            else { println!("{:08X};", val_str_tok.parse::<u32>().unwrap()); }
-        }
-    }
-    if word_count < 0xFF { println!("\t[{:02X}..FF] : 00000000;", word_count); }
-    else if word_count > DEPTH {
-        eprintln!("ERROR: Too much data! There can be at most {} words of data!", DEPTH);
+    if !file.to_lowercase().ends_with(".asm") {
+        eprintln!("ERROR: Use an .asm mips assembly file as an argument!");
         std::process::exit(1);
     }
 
-    // End dmemory.mif
-    println!("End;");
-
-    // Debug information
-    //println!("{:?}", jump_labels);
-    //println!("{:?}", data_labels);
-}
+    let file = Path::new(file);
+    if !file.is_file() {
+        eprintln!("ERROR: Can't read file or file doesn't exist!");
+        std::process::exit(1);
+    }
 
-fn parse_first_token(s : &String) -> Token {
-    // Check if it is an directive
-    if s.starts_with(".") {
-        match &s[1..] {
-            "globl" => Token::Global,
-            "text" => Token::Text,
-            "data" => Token::Data,
-            "word" => Token::Word,
-            _ => Token::NotFound,
+    // Each file goes on top of the ones before it
+    for path in &isa_files {
+        match isa::load(path) {
+            Ok(instructions) => opts.instructions.extend(instructions),
+            Err(errors) => {
+                for e in &errors { eprintln!("{}\n", e); }
+                eprintln!("ERROR: Found {} error(s) in {}, nothing was assembled.", errors.len(), path.display());
+                std::process::exit(1);
+            }
         }
     }
-    // Check if it is an label
-    else if s.chars().last().unwrap_or(' ') == ':'{
-        Token::Label
-    }
-    else {
-        match s.as_str() {
-            "sw" => Token::SW,
-            "lw" => Token::LW,
-            "addi" => Token::ADDI,
-            "beq" => Token::BEQ,
-            "bne" => Token::BNE,
-            "and" => Token::AND,
-            "or" => Token::OR,
-            "add" => Token::ADD,
-            "sub" => Token::SUB,
-            "slt" => Token::SLT,
-            "srl" => Token::SRL,
-            "sll" => Token::SLL,
-            "jr" => Token::JR,
-            "j" => Token::J,
-            "jal" => Token::JAL,
-            _ => Token::NotFound,
-        }
+
+    // A built-in name, or else a file listing the allowed mnemonics
+    let mut profile_file : Option<&Path> = None;
+    if let Some(name) = profile {
+        opts.profile = match isa::Profile::named(name) {
+            Some(profile) => Some(profile),
+            None if Path::new(name).is_file() => match isa::load_profile(Path::new(name), &opts.instructions) {
+                Ok(profile) => {
+                    profile_file = Some(Path::new(name));
+                    Some(profile)
+                }
+                Err(errors) => {
+                    for e in &errors { eprintln!("{}\n", e); }
+                    eprintln!("ERROR: Found {} error(s) in {}, nothing was assembled.", errors.len(), name);
+                    std::process::exit(1);
+                }
+            },
+            None => {
+                eprintln!("ERROR: {} is not a profile file or one of single-cycle-15, mips-i and mips32r2!", name);
+                std::process::exit(1);
+            }
+        };
     }
-}
 
-fn parse_token(z : &String) -> Token {
-    let is_immediate = z.parse::<i16>();
-    let mut tok = match is_immediate {
-        Ok(_) => Token::I,
-        Err(_) => Token::NotFound,
-    };
-    if tok == Token::NotFound {
-        // Check if it is an immediate value in hexadecimal
-        if z.starts_with("0x") && u32::from_str_radix(&z[2..], 16).is_ok() {
-            tok = Token::I;
-        }
-        // Check if it is an directive
-        else if z.starts_with(".") {
-            tok = match &z[1..] {
-                "globl" => Token::Global,
-                "text" => Token::Text,
-                "data" => Token::Data,
-                "word" => Token::Word,
-                "space" => Token::Space,
-                _ => Token::NotFound,
+    match quamgears::assemble_file(file, &opts) {
+        Ok(program) => {
+            // The target (where the output is sent, the .mif named after the source unless told otherwise) depends on it,
+            // the instruction set and profile files and everything it includes, and each of those gets an empty rule
+            // so make doesn't fail once it's removed
+            if let Some(depfile) = depfile {
+                let others : Vec<&Path> = isa_files.iter().copied().chain(profile_file).chain(program.includes.iter().map(PathBuf::as_path)).collect();
+                let target : PathBuf = depfile_target.map_or_else(|| file.with_extension("mif"), PathBuf::from);
+                let mut deps = format!("{}:", make_escape(&target));
+                for dep in std::iter::once(file).chain(others.iter().copied()) {
+                    deps += &format!(" {}", make_escape(dep));
+                }
+                deps += "\n";
+                for dep in &others {
+                    deps += &format!("\n{}:\n", make_escape(dep));
+                }
+                if std::fs::write(depfile, deps).is_err() {
+                    eprintln!("ERROR: Can't write the dependency file {}!", depfile);
+                    std::process::exit(1);
+                }
             }
+            print!("{}", mif::program_mif(&program.text, opts.depth));
+            print!("{}", mif::data_mif(&program.data, opts.depth));
         }
-        // Check if it is an label
-        else if z.chars().last().unwrap_or(' ') == ':'{
-            tok = Token::Label;
-        }
-        //else { tok = parse_token(&z); }
-        else {
-            tok = match z.as_str() {
-                "$zero" => Token::Zero,
-                "$at" => Token::AT,
-                "$v0" => Token::V0,
-                "$v1" => Token::V1,
-                "$a0" => Token::A0,
-                "$a1" => Token::A1,
-                "$a2" => Token::A2,
-                "$a3" => Token::A3,
-                "$t0" => Token::T0,
-                "$t1" => Token::T1,
-                "$t2" => Token::T2,
-                "$t3" => Token::T3,
-                "$t4" => Token::T4,
-                "$t5" => Token::T5,
-                "$t6" => Token::T6,
-                "$t7" => Token::T7,
-                "$s0" => Token::S0,
-                "$s1" => Token::S1,
-                "$s2" => Token::S2,
-                "$s3" => Token::S3,
-                "$s4" => Token::S4,
-                "$s5" => Token::S5,
-                "$s6" => Token::S6,
-                "$s7" => Token::S7,
-                "$t8" => Token::T8,
-                "$t9" => Token::T9,
-                "$k0" => Token::K0,
-                "$k1" => Token::K1,
-                "$gp" => Token::GP,
-                "$sp" => Token::SP,
-                "$fp" => Token::FP,
-                "$ra" => Token::RA,
-                "sw" => Token::SW,
-                "lw" => Token::LW,
-                "addi" => Token::ADDI,
-                "beq" => Token::BEQ,
-                "bne" => Token::BNE,
-                "and" => Token::AND,
-                "or" => Token::OR,
-                "add" => Token::ADD,
-                "sub" => Token::SUB,
-                "slt" => Token::SLT,
-                "srl" => Token::SRL,
-                "sll" => Token::SLL,
-                "jr" => Token::JR,
-                "j" => Token::J,
-                "jal" => Token::JAL,
-                _ => Token::NotFound,
-            };
+        Err(errors) => {
+            for e in &errors { eprintln!("{}\n", e); }
+            eprintln!("ERROR: Found {} error(s), nothing was assembled.", errors.len());
+            std::process::exit(1);
         }
     }
-    tok
 }
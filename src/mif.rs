@@ -0,0 +1,39 @@
+// Renders memory contents as Memory Initialization Files for Quartus
+// program.mif = PROGram RAM Memory Initialization File
+// dmemory.mif = Data MEMORY Memory Initialization File
+
+use std::fmt::Write;
+
+const TOP_PROGRAM_COMMENT : &str = "MIPS Instruction Memory Initialization File";
+const TOP_DATA_COMMENT : &str = "MIPS Data Memory Initialization File";
+const WIDTH : u32 = 32;
+const ADDRESS_RADIX : &str = "HEX";
+const DATA_RADIX : &str = "HEX";
+
+pub fn program_mif(words : &[u32], depth : u32) -> String {
+    render(words, depth, TOP_PROGRAM_COMMENT, ": ")
+}
+
+pub fn data_mif(words : &[u32], depth : u32) -> String {
+    render(words, depth, TOP_DATA_COMMENT, " : ")
+}
+
+fn render(words : &[u32], depth : u32, comment : &str, sep : &str) -> String {
+    let mut out = String::new();
+    writeln!(out, "-- {}", comment).unwrap();
+    writeln!(out, "Depth = {};", depth).unwrap();
+    writeln!(out, "Width = {};", WIDTH).unwrap();
+    writeln!(out, "Address_radix = {};", ADDRESS_RADIX).unwrap();
+    writeln!(out, "Data_radix = {};", DATA_RADIX).unwrap();
+    writeln!(out, "Content").unwrap();
+    writeln!(out, "Begin").unwrap();
+    for (i, word) in words.iter().enumerate() {
+        writeln!(out, "\t{:02X}{}{:08X};", i, sep, word).unwrap();
+    }
+    // Everything after the last word is zeroed in one range
+    if (words.len() as u32) < depth {
+        writeln!(out, "\t[{:02X}..{:02X}]{}00000000;", words.len(), depth - 1, sep).unwrap();
+    }
+    writeln!(out, "End;").unwrap();
+    out
+}
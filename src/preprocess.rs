@@ -0,0 +1,391 @@
+// Runs over the lines of tokens before the label pass: .include files are read in place,
+// lines in a false .if are dropped, macro definitions are taken out and every call to one
+// is replaced by its body, with the arguments put in place of the parameters.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::assembler::split_labels;
+use crate::error::{AssembleError, ErrorKind};
+use crate::expr;
+use crate::lexer::{self, Span, Token, TokenKind};
+use crate::Options;
+
+// Calls inside calls, so a macro that calls itself stops somewhere
+const MAX_DEPTH : usize = 64;
+
+struct Macro {
+    name : String,
+    params : Vec<String>, // Without the '%'
+    body : Vec<Vec<Token>>,
+    labels : Vec<String>, // Defined in the body, renamed in every expansion so they don't clash
+    broken : bool, // Its definition had errors, so calls to it are quietly dropped instead of adding more
+}
+
+// An .if (or .ifdef/.ifndef) waiting for its .endif
+struct Cond {
+    directive : String,
+    span : Span,  // The opening line, for when .endif is missing
+    active : bool, // Lines are kept right now
+    taken : bool, // Some branch was (or can't be) kept already, so the next ones are dropped
+    seen_else : bool,
+}
+
+// A span with expansion n came out of the macro call expansions[n - 1]
+pub struct Expansion {
+    pub name : String,
+    pub call : Span,
+}
+
+// A file read from disk, and where its text went in the combined source
+pub struct SourceFile {
+    pub path : PathBuf,
+    pub start : usize,
+    pub end : usize,
+    pub included_at : Option<Span>, // The .include line that brought it in, None for the main file
+}
+
+pub struct Output {
+    pub lines : Vec<Vec<Token>>,
+    pub expansions : Vec<Expansion>,
+    // The main file followed by every included one, which is what all the spans point into
+    pub source : String,
+    pub files : Vec<SourceFile>, // The main file first when it was read from disk
+}
+
+struct Preprocessor<'o, 'e> {
+    opts : &'o Options,
+    src : String,
+    files : Vec<SourceFile>,
+    including : Vec<PathBuf>, // Files being read right now, to catch one that includes itself
+    errors : &'e mut Vec<AssembleError>,
+    macros : Vec<Macro>,
+    expansions : Vec<Expansion>,
+    // Constants .if can use: the -D ones and those defined so far, None when their value needs a label
+    constants : HashMap<String, Option<i64>>,
+    out : Vec<Vec<Token>>,
+}
+
+// path is where text came from, if it's a file, so .include can look next to it
+pub fn preprocess(text : &str, path : Option<&Path>, opts : &Options, errors : &mut Vec<AssembleError>) -> Output {
+    let mut pp = Preprocessor { opts, src: String::new(), files: Vec::new(), including: Vec::new(),
+        errors, macros: Vec::new(), expansions: Vec::new(), out: Vec::new(),
+        constants: opts.defines.iter().map(|(name, value)| (name.clone(), Some(*value))).collect() };
+    if let Some(path) = path {
+        pp.including.push(fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+    }
+    let lines = pp.load(text);
+    if let Some(path) = path {
+        pp.files.push(SourceFile { path: path.to_path_buf(), start: 0, end: pp.src.len(), included_at: None });
+    }
+    pp.file(lines, path.and_then(Path::parent).unwrap_or(Path::new("")));
+    Output { lines: pp.out, expansions: pp.expansions, source: pp.src, files: pp.files }
+}
+
+fn is_directive(line : &[Token], name : &str) -> bool {
+    matches!(&line[0].kind, TokenKind::Directive(d) if d == name)
+}
+
+// %name, written without spaces
+fn param<'t>(percent : &Token, next : Option<&'t Token>) -> Option<&'t str> {
+    match next {
+        Some(Token { kind: TokenKind::Identifier(name), span }) if percent.kind == TokenKind::Percent && span.start == percent.span.end => Some(name),
+        _ => None,
+    }
+}
+
+impl Preprocessor<'_, '_> {
+    fn error(&mut self, kind : ErrorKind, span : Span) {
+        self.errors.push(AssembleError::new(kind, span, &self.src));
+    }
+
+    // Adds the text of a file to the source and splits its tokens into lines
+    fn load(&mut self, text : &str) -> Vec<Vec<Token>> {
+        let base : usize = self.src.len();
+        self.src.push_str(text);
+        if !text.ends_with('\n') { self.src.push('\n'); }
+        // The lexer only sees this file, so its spans start at 0
+        let mut errors : Vec<AssembleError> = Vec::new();
+        let mut tokens : Vec<Token> = lexer::tokenize(&self.src[base..], &mut errors);
+        for span in tokens.iter_mut().map(|t| &mut t.span).chain(errors.iter_mut().filter_map(|e| e.span.as_mut())) {
+            span.start += base;
+            span.end += base;
+        }
+        self.errors.append(&mut errors);
+        tokens.split(|t| t.kind == TokenKind::Newline).filter(|l| !l.is_empty()).map(|l| l.to_vec()).collect()
+    }
+
+    // The lines of a single file, where dir is the directory it is in
+    fn file(&mut self, lines : Vec<Vec<Token>>, dir : &Path) {
+        self.block(lines, dir, 0);
+    }
+
+    // The lines of a file or a macro expansion, which have to close every .if they open.
+    // depth counts the macro calls that got here
+    fn block(&mut self, lines : Vec<Vec<Token>>, dir : &Path, depth : usize) {
+        let mut conds : Vec<Cond> = Vec::new();
+        let mut lines = lines.into_iter();
+        while let Some(line) = lines.next() {
+            if self.conditional(&line, &mut conds) { continue; }
+            if conds.last().is_some_and(|c| !c.active) { continue; }
+            if is_directive(&line, "macro") { self.define(&line, &mut lines); }
+            else if is_directive(&line, "end_macro") { self.error(ErrorKind::StrayEndMacro, line[0].span); }
+            else if is_directive(&line, "include") { self.include(&line, dir); }
+            else { self.line(line, dir, depth); }
+        }
+        for cond in conds {
+            self.error(ErrorKind::UnterminatedIf(format!(".{}", cond.directive)), cond.span);
+        }
+    }
+
+    // Handles .if, .ifdef, .ifndef, .elseif, .else and .endif, returning whether line was one of them
+    fn conditional(&mut self, line : &[Token], conds : &mut Vec<Cond>) -> bool {
+        let TokenKind::Directive(d) = &line[0].kind else { return false };
+        let span = line[0].span;
+        // Conditions inside dropped lines aren't even looked at
+        let outer_active : bool = conds.iter().all(|c| c.active);
+        match d.as_str() {
+            "if" | "ifdef" | "ifndef" => {
+                let active : bool = outer_active && self.condition(d, line);
+                conds.push(Cond { directive: d.clone(), span, active, taken: active || !outer_active, seen_else: false });
+            }
+            "elseif" | "else" | "endif" => {
+                let Some(cond) = conds.last_mut() else {
+                    self.error(ErrorKind::StrayConditional(format!(".{}", d)), span);
+                    return true;
+                };
+                if d == "endif" {
+                    conds.pop();
+                    return true;
+                }
+                if cond.seen_else {
+                    self.error(ErrorKind::ElseAfterElse(format!(".{}", d)), span);
+                    cond.active = false;
+                    return true;
+                }
+                cond.active = !cond.taken && (d == "else" || self.condition(d, line));
+                cond.taken |= cond.active;
+                cond.seen_else = d == "else";
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // Whether the condition of an .if, .elseif, .ifdef or .ifndef line holds
+    fn condition(&mut self, d : &str, line : &[Token]) -> bool {
+        let span = Span::covering(line);
+        match (d, &line[1..]) {
+            ("ifdef", [Token { kind: TokenKind::Identifier(name), .. }]) => self.constants.contains_key(name),
+            ("ifndef", [Token { kind: TokenKind::Identifier(name), .. }]) => !self.constants.contains_key(name),
+            ("ifdef" | "ifndef", _) => {
+                self.error(ErrorKind::ExpectedConditionName(format!(".{}", d)), span);
+                false
+            }
+            (_, []) => {
+                self.error(ErrorKind::MissingOperand, line[0].span);
+                false
+            }
+            (_, tokens) => {
+                let value = expr::parse(tokens, &self.src).and_then(|e| {
+                    e.eval(&mut |name| self.constants.get(name).copied().flatten(), &self.src)
+                });
+                match value {
+                    Ok(value) => value != 0,
+                    Err(mut e) => {
+                        // Labels don't have an address yet, so anything but a known constant is an error
+                        if let ErrorKind::UndefinedLabel(name) = e.kind { e.kind = ErrorKind::NotKnownYet(name); }
+                        self.errors.push(e);
+                        false
+                    }
+                }
+            }
+        }
+    }
+
+    // Keeps track of .eqv, .equ and .set for .if, giving up on the value of one that needs a label
+    fn track_constant(&mut self, body : &[Token]) {
+        let [Token { kind: TokenKind::Directive(d), .. }, Token { kind: TokenKind::Identifier(name), .. }, rest @ ..] = body else { return };
+        if d != "eqv" && d != "equ" && d != "set" { return; }
+        let rest = match rest {
+            [Token { kind: TokenKind::Comma, .. }, tail @ ..] => tail,
+            _ => rest,
+        };
+        // Errors in the definition are reported by the label pass
+        let value : Option<i64> = expr::parse(rest, &self.src).ok()
+            .and_then(|e| e.eval(&mut |name| self.constants.get(name).copied().flatten(), &self.src).ok());
+        self.constants.insert(name.clone(), value);
+    }
+
+    // .include "file", looked for next to the file including it, then in each -I directory
+    fn include(&mut self, line : &[Token], dir : &Path) {
+        let span = Span::covering(line);
+        let [_, Token { kind: TokenKind::Str(name), .. }] = line else {
+            self.error(ErrorKind::ExpectedIncludeFile, span);
+            return;
+        };
+        let found : Option<PathBuf> = std::iter::once(dir).chain(self.opts.include_paths.iter().map(PathBuf::as_path))
+            .map(|d| d.join(name)).find(|p| p.is_file());
+        let Some(path) = found else {
+            self.error(ErrorKind::IncludeNotFound(name.clone()), span);
+            return;
+        };
+        let canonical : PathBuf = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if self.including.contains(&canonical) {
+            self.error(ErrorKind::IncludeCycle(name.clone()), span);
+            return;
+        }
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                self.error(ErrorKind::CantRead(path.display().to_string(), e.to_string()), span);
+                return;
+            }
+        };
+        let start : usize = self.src.len();
+        let lines = self.load(&text);
+        self.files.push(SourceFile { path: path.clone(), start, end: self.src.len(), included_at: Some(span) });
+        self.including.push(canonical);
+        self.file(lines, path.parent().unwrap_or(Path::new("")));
+        self.including.pop();
+    }
+
+    // .macro name(%a, %b) up to .end_macro, the parentheses and commas being optional like in MARS
+    fn define(&mut self, header : &[Token], lines : &mut impl Iterator<Item = Vec<Token>>) {
+        let mut body : Vec<Vec<Token>> = Vec::new();
+        let mut closed : bool = false;
+        for line in lines.by_ref() {
+            if is_directive(&line, "end_macro") {
+                closed = true;
+                break;
+            }
+            if is_directive(&line, "macro") { self.error(ErrorKind::NestedMacro, line[0].span); }
+            else { body.push(line); }
+        }
+
+        let Some(Token { kind: TokenKind::Identifier(name), span: name_span }) = header.get(1) else {
+            self.error(ErrorKind::ExpectedMacroName, header[0].span);
+            return;
+        };
+        if !closed {
+            self.error(ErrorKind::UnterminatedMacro(name.clone()), header[0].span);
+            return;
+        }
+        let mut params : Vec<String> = Vec::new();
+        let mut broken : bool = false;
+        let mut toks = header[2..].iter();
+        while let Some(tok) = toks.next() {
+            match &tok.kind {
+                TokenKind::LParen | TokenKind::RParen | TokenKind::Comma => (),
+                _ => match param(tok, toks.as_slice().first()) {
+                    Some(p) => {
+                        params.push(String::from(p));
+                        toks.next();
+                    }
+                    None => {
+                        self.error(ErrorKind::BadMacroParam, tok.span);
+                        broken = true;
+                    }
+                },
+            }
+        }
+        // Every %name in the body should be a parameter, other than %hi and %lo
+        for line in &body {
+            for (i, tok) in line.iter().enumerate() {
+                if let Some(p) = param(tok, line.get(i + 1)) {
+                    let operator = (p.eq_ignore_ascii_case("hi") || p.eq_ignore_ascii_case("lo"))
+                        && matches!(line.get(i + 2), Some(Token { kind: TokenKind::LParen, .. }));
+                    if !operator && !params.iter().any(|q| q == p) {
+                        self.error(ErrorKind::UnknownMacroParam(String::from(p)), Span::covering(&line[i..=i + 1]));
+                        broken = true;
+                    }
+                }
+            }
+        }
+        if self.macros.iter().any(|m| m.name == *name && m.params.len() == params.len()) {
+            self.error(ErrorKind::DuplicateMacro(name.clone(), params.len()), *name_span);
+            return;
+        }
+        let labels : Vec<String> = body.iter().flat_map(|line| split_labels(line).0).map(|tok| match &tok.kind {
+            TokenKind::Identifier(label) => label.clone(),
+            _ => unreachable!(),
+        }).collect();
+        self.macros.push(Macro { name: name.clone(), params, body, labels, broken });
+    }
+
+    fn line(&mut self, line : Vec<Token>, dir : &Path, depth : usize) {
+        let (labels, body) = split_labels(&line);
+        match body.first() {
+            Some(Token { kind: TokenKind::Mnemonic(m), .. }) if self.macros.iter().any(|mac| mac.name == *m) => {
+                // Labels before the call stay here, on a line of their own
+                if !labels.is_empty() {
+                    self.out.push(line[..line.len() - body.len()].to_vec());
+                }
+                self.expand(body, dir, depth);
+            }
+            _ => {
+                self.track_constant(body);
+                self.out.push(line);
+            }
+        }
+    }
+
+    fn expand(&mut self, call : &[Token], dir : &Path, depth : usize) {
+        let TokenKind::Mnemonic(name) = &call[0].kind else { unreachable!() };
+        let span = Span::covering(call);
+        let args : Vec<&[Token]> = macro_args(&call[1..]);
+        let Some(mac) = self.macros.iter().find(|m| m.name == *name && m.params.len() == args.len()) else {
+            self.error(ErrorKind::MacroArgCount(name.clone(), args.len()), span);
+            return;
+        };
+        if mac.broken { return; }
+        if depth == MAX_DEPTH {
+            self.error(ErrorKind::MacroTooDeep(name.clone(), MAX_DEPTH), span);
+            return;
+        }
+        self.expansions.push(Expansion { name: name.clone(), call: span });
+        let expansion = self.expansions.len() as u32;
+
+        let mut lines : Vec<Vec<Token>> = Vec::new();
+        for body_line in &mac.body {
+            let mut line : Vec<Token> = Vec::new();
+            let mut toks = body_line.iter();
+            while let Some(tok) = toks.next() {
+                // Arguments keep the spans of the call, everything else points into the body
+                if let Some(i) = param(tok, toks.as_slice().first()).and_then(|p| mac.params.iter().position(|q| q == p)) {
+                    line.extend(args[i].iter().cloned());
+                    toks.next();
+                    continue;
+                }
+                let mut tok = tok.clone();
+                tok.span.expansion = expansion;
+                if let TokenKind::Identifier(label) = &tok.kind {
+                    if mac.labels.contains(label) { tok.kind = TokenKind::Identifier(format!("{}_M{}", label, expansion)); }
+                }
+                line.push(tok);
+            }
+            if !line.is_empty() { lines.push(line); }
+        }
+        self.block(lines, dir, depth + 1);
+    }
+}
+
+// name(a, b) or name a, b, where each argument can be several tokens like 4($sp)
+fn macro_args(tokens : &[Token]) -> Vec<&[Token]> {
+    let mut tokens = tokens;
+    if let [Token { kind: TokenKind::LParen, .. }, inner @ .., Token { kind: TokenKind::RParen, .. }] = tokens {
+        // Only when the first parenthesis closes at the very end, not for (a)+(b)
+        let mut depth : i32 = 0;
+        let whole = tokens.iter().enumerate().all(|(i, tok)| {
+            match tok.kind {
+                TokenKind::LParen => depth += 1,
+                TokenKind::RParen => depth -= 1,
+                _ => (),
+            }
+            depth > 0 || i == tokens.len() - 1
+        });
+        if whole { tokens = inner; }
+    }
+    if tokens.is_empty() { return Vec::new(); }
+    tokens.split(|t| t.kind == TokenKind::Comma).collect()
+}
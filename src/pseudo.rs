@@ -0,0 +1,130 @@
+// Pseudo-instructions: mnemonics that aren't in the instruction set but are written as if they were,
+// and turn into one or more real instructions, with $at holding anything in between.
+
+use crate::isa::{self, InstrSpec};
+use crate::lexer::{self, Span, Token, TokenKind};
+
+// What a written operand has to be
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum Arg { Reg, Imm } // Imm is any expression, labels included
+
+// Values an expansion can take, for pseudo-instructions with more than one
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum Fits {
+    Signed16,
+    Unsigned16,
+    UpperHalf, // Only the upper 16 bits are set
+    Any,
+}
+
+impl Fits {
+    pub fn check(self, value : i64) -> bool {
+        match self {
+            Fits::Signed16 => (-0x8000..=0x7FFF).contains(&value),
+            Fits::Unsigned16 => (0..=0xFFFF).contains(&value),
+            Fits::UpperHalf => value & 0xFFFF == 0 && (-0x8000_0000..=0xFFFF_FFFF).contains(&value),
+            Fits::Any => true,
+        }
+    }
+}
+
+// Real instruction lines, where %1, %2... stand for the written operands
+#[derive(Debug)]
+pub struct Expansion {
+    pub fits : Fits,
+    pub lines : &'static [&'static str],
+}
+
+#[derive(Debug)]
+pub struct Pseudo {
+    pub mnemonic : &'static str,
+    pub operands : &'static [Arg],
+    pub imm : Option<usize>, // The operand that picks the expansion, also checked to fit in 32 bits
+    pub expansions : &'static [Expansion], // Shortest first, the last one fits anything
+}
+
+impl Pseudo {
+    // The shortest expansion that fits value, or the last one when it isn't known yet
+    pub fn choose(&self, value : Option<i64>) -> &'static Expansion {
+        let expansions : &'static [Expansion] = self.expansions;
+        expansions.iter().find(|e| value.is_some_and(|v| e.fits.check(v))).unwrap_or(&expansions[expansions.len() - 1])
+    }
+}
+
+const fn any(lines : &'static [&'static str]) -> Expansion {
+    Expansion { fits: Fits::Any, lines }
+}
+
+const fn p(mnemonic : &'static str, operands : &'static [Arg], expansions : &'static [Expansion]) -> Pseudo {
+    Pseudo { mnemonic, operands, imm: None, expansions }
+}
+
+// li and la load any 32 bit value, in a single instruction when it's known early enough to fit one
+const LOAD : &[Expansion] = &[
+    Expansion { fits: Fits::Signed16, lines: &["addiu %1, $zero, %2"] },
+    Expansion { fits: Fits::Unsigned16, lines: &["ori %1, $zero, %2"] },
+    Expansion { fits: Fits::UpperHalf, lines: &["lui %1, ((%2) >> 16) & 0xFFFF"] },
+    any(&["lui $at, ((%2) >> 16) & 0xFFFF", "ori %1, $at, (%2) & 0xFFFF"]),
+];
+
+use Arg::*;
+
+pub const PSEUDOS : &[Pseudo] = &[
+    p("move", &[Reg, Reg], &[any(&["addu %1, $zero, %2"])]),
+    Pseudo { imm: Some(1), ..p("li", &[Reg, Imm], LOAD) },
+    Pseudo { imm: Some(1), ..p("la", &[Reg, Imm], LOAD) },
+    p("not", &[Reg, Reg], &[any(&["nor %1, %2, $zero"])]),
+    p("neg", &[Reg, Reg], &[any(&["sub %1, $zero, %2"])]),
+    // $at is all ones for a negative number and 0 otherwise, so this flips it and adds 1 only then
+    p("abs", &[Reg, Reg], &[any(&["sra $at, %2, 31", "xor %1, %2, $at", "subu %1, %1, $at"])]),
+    // Only with an immediate, mul with three registers is a real instruction
+    Pseudo { imm: Some(2), ..p("mul", &[Reg, Reg, Imm], &[
+        Expansion { fits: Fits::Signed16, lines: &["addiu $at, $zero, %3", "mul %1, %2, $at"] },
+        Expansion { fits: Fits::Unsigned16, lines: &["ori $at, $zero, %3", "mul %1, %2, $at"] },
+        Expansion { fits: Fits::UpperHalf, lines: &["lui $at, ((%3) >> 16) & 0xFFFF", "mul %1, %2, $at"] },
+        any(&["lui $at, ((%3) >> 16) & 0xFFFF", "ori $at, $at, (%3) & 0xFFFF", "mul %1, %2, $at"]),
+    ]) },
+    // Branches
+    p("b", &[Imm], &[any(&["beq $zero, $zero, %1"])]),
+    p("beqz", &[Reg, Imm], &[any(&["beq %1, $zero, %2"])]),
+    p("bnez", &[Reg, Imm], &[any(&["bne %1, $zero, %2"])]),
+    p("blt", &[Reg, Reg, Imm], &[any(&["slt $at, %1, %2", "bne $at, $zero, %3"])]),
+    p("bgt", &[Reg, Reg, Imm], &[any(&["slt $at, %2, %1", "bne $at, $zero, %3"])]),
+    p("ble", &[Reg, Reg, Imm], &[any(&["slt $at, %2, %1", "beq $at, $zero, %3"])]),
+    p("bge", &[Reg, Reg, Imm], &[any(&["slt $at, %1, %2", "beq $at, $zero, %3"])]),
+    p("bltu", &[Reg, Reg, Imm], &[any(&["sltu $at, %1, %2", "bne $at, $zero, %3"])]),
+    p("bgtu", &[Reg, Reg, Imm], &[any(&["sltu $at, %2, %1", "bne $at, $zero, %3"])]),
+    p("bleu", &[Reg, Reg, Imm], &[any(&["sltu $at, %2, %1", "beq $at, $zero, %3"])]),
+    p("bgeu", &[Reg, Reg, Imm], &[any(&["sltu $at, %1, %2", "beq $at, $zero, %3"])]),
+];
+
+// Mnemonics are matched in any case, and regs says which written operands are registers.
+// An instruction in extra replaces any pseudo-instruction with its name. A built-in instruction
+// with the same name gets whatever doesn't fit, otherwise the first form with that name does
+// (and will complain about its operands)
+pub fn find_pseudo(extra : &[InstrSpec], mnemonic : &str, regs : &[bool]) -> Option<&'static Pseudo> {
+    if extra.iter().any(|spec| spec.mnemonic.eq_ignore_ascii_case(mnemonic)) { return None; }
+    let mut forms = PSEUDOS.iter().filter(|pseudo| pseudo.mnemonic.eq_ignore_ascii_case(mnemonic));
+    let first = forms.clone().next();
+    let exact = forms.find(|pseudo| pseudo.operands.len() == regs.len()
+        && pseudo.operands.iter().zip(regs).all(|(&arg, &reg)| (arg == Reg) == reg));
+    if isa::find_instruction(extra, mnemonic, regs.len()).is_some() { exact } else { exact.or(first) }
+}
+
+// The lines of an expansion with the written operands put in, where everything that came from
+// the expansion itself points at the pseudo-instruction's mnemonic (at)
+pub fn expand(expansion : &Expansion, at : Span, ops : &[&[Token]]) -> Vec<Vec<Token>> {
+    expansion.lines.iter().map(|text| {
+        let mut line : Vec<Token> = Vec::new();
+        let mut template = lexer::tokenize(text, &mut Vec::new()).into_iter().peekable();
+        while let Some(tok) = template.next() {
+            if let (TokenKind::Percent, Some(Token { kind: TokenKind::Integer(n), .. })) = (&tok.kind, template.peek()) {
+                line.extend_from_slice(ops[*n as usize - 1]);
+                template.next();
+                continue;
+            }
+            line.push(Token { span: at, ..tok });
+        }
+        line
+    }).collect()
+}
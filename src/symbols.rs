@@ -0,0 +1,110 @@
+// Every name an expression can use: jump labels, data labels and the constants
+// defined with .eqv, .equ and .set
+
+use std::collections::HashMap;
+use crate::error::{AssembleError, ErrorKind};
+use crate::expr::Expr;
+use crate::lexer::Span;
+use crate::AddressModel;
+
+pub struct Constant {
+    pub name : String,
+    pub value : Expr,
+    pub span : Option<Span>, // The name in the definition, None for -D constants
+    pub set : bool,  // Defined with .set, so it can be defined again with .set
+}
+
+// Where a constant is while its value is worked out
+#[derive(Copy,Clone)]
+enum Resolved { Pending, Busy, Done(i64) }
+
+#[derive(Default)]
+pub struct Symbols {
+    pub jump_labels : HashMap<String, u32>,
+    pub data_labels : HashMap<String, u32>,
+    // Every definition in file order, so a name defined again with .set has several
+    pub constants : Vec<Constant>,
+    values : Vec<i64>, // Filled by resolve, one per definition
+    pub model : AddressModel, // What the labels are worth
+}
+
+impl Symbols {
+    pub fn is_label(&self, name : &str) -> bool {
+        self.jump_labels.contains_key(name) || self.data_labels.contains_key(name)
+    }
+
+    // Jump labels are word indexes and data labels byte offsets, which the model turns into addresses
+    pub fn label(&self, name : &str) -> Option<i64> {
+        if let Some(&offset) = self.data_labels.get(name) { Some(self.model.data_address(offset)) }
+        else { self.jump_labels.get(name).map(|&index| self.model.text_address(index)) }
+    }
+
+    // The definition of name seen by a line that comes after the first scope definitions:
+    // the last one before it, or the first one in the file for a name only defined later
+    fn constant(&self, name : &str, scope : usize) -> Option<usize> {
+        self.constants[..scope].iter().rposition(|c| c.name == name)
+            .or_else(|| self.constants.iter().position(|c| c.name == name))
+    }
+
+    // Value of a name for a line that comes after the first scope constant definitions
+    pub fn value(&self, name : &str, scope : usize) -> Option<i64> {
+        self.label(name).or_else(|| self.constant(name, scope).map(|i| self.values[i]))
+    }
+
+    // Value of an expression in the label pass, before any constant has been worked out,
+    // so only numbers and constants that don't need a label can be used
+    pub fn early_value(&self, e : &Expr, scope : usize, src : &str) -> Result<i64, AssembleError> {
+        e.eval(&mut |name| self.early_constant(name, scope, 0, src), src).map_err(|mut e| {
+            if let ErrorKind::UndefinedLabel(name) = e.kind { e.kind = ErrorKind::NotKnownYet(name); }
+            e
+        })
+    }
+
+    fn early_constant(&self, name : &str, scope : usize, depth : usize, src : &str) -> Option<i64> {
+        // A constant that depends on itself never ends, so give up after a while
+        if depth == 64 || self.is_label(name) { return None; }
+        let i = self.constant(name, scope)?;
+        self.constants[i].value.eval(&mut |name| self.early_constant(name, i, depth + 1, src), src).ok()
+    }
+
+    // Works out the value of every constant once the labels have their addresses.
+    // A constant that can't be worked out reports why and is worth 0 from then on
+    pub fn resolve(&mut self, src : &str, errors : &mut Vec<AssembleError>) {
+        let mut resolved : Vec<Resolved> = vec![Resolved::Pending; self.constants.len()];
+        for i in 0..self.constants.len() {
+            self.resolve_one(i, &mut resolved, src, errors);
+        }
+        self.values = resolved.iter().map(|r| match r {
+            Resolved::Done(v) => *v,
+            _ => unreachable!(),
+        }).collect();
+    }
+
+    fn resolve_one(&self, i : usize, resolved : &mut [Resolved], src : &str, errors : &mut Vec<AssembleError>) -> i64 {
+        match resolved[i] {
+            Resolved::Done(v) => return v,
+            Resolved::Busy => {
+                // Only constants from the source can use other names, so there's always a span
+                let span = self.constants[i].span.unwrap();
+                errors.push(AssembleError::new(ErrorKind::CircularConstant(self.constants[i].name.clone()), span, src));
+                resolved[i] = Resolved::Done(0);
+                return 0;
+            }
+            Resolved::Pending => resolved[i] = Resolved::Busy,
+        }
+        let mut nested : Vec<AssembleError> = Vec::new();
+        let value = self.constants[i].value.eval(&mut |name| {
+            self.label(name).or_else(|| {
+                let j = self.constant(name, i)?;
+                Some(self.resolve_one(j, resolved, src, &mut nested))
+            })
+        }, src);
+        errors.append(&mut nested);
+        let value = value.unwrap_or_else(|e| {
+            errors.push(e);
+            0
+        });
+        resolved[i] = Resolved::Done(value);
+        value
+    }
+}
@@ -0,0 +1,42 @@
+// Address models: word or byte addresses, where .text and .data start, and data reached from $gp
+
+mod common;
+
+use common::{first_error, ok};
+use quamgears::{AddressModel, ErrorKind, Options};
+
+#[test]
+fn uses_the_addresses_of_the_model() {
+    let src = "main: nop\nfoo: beq $zero, $zero, foo\n j foo\n.data\nx: .word x\n";
+    let word = ok(src, &Options::default());
+    assert_eq!(word.text[1..], [0x1000_FFFF, 0x0800_0001]);
+    assert_eq!(word.data[0], 0);
+
+    let opts = Options { address_model: AddressModel::MARS, ..Options::default() };
+    let mars = ok(src, &opts);
+    assert_eq!(mars.text[1..], [0x1000_FFFF, 0x0810_0001]);
+    assert_eq!(mars.data[0], 0x1001_0000);
+    assert_eq!((mars.symbols["foo"].address, mars.symbols["foo"].offset), (0x0040_0004, 1));
+    assert_eq!((mars.symbols["x"].address, mars.symbols["x"].offset), (0x1001_0000, 0));
+}
+
+#[test]
+fn reaches_data_from_gp() {
+    let src = "main: lw $t0, x\n lb $t1, x+1\n.data\nx: .word 1\n";
+    let mars = Options { address_model: AddressModel::MARS, ..Options::default() };
+    assert_eq!(first_error(src, &mars).kind, ErrorKind::DataOutOfReach(0x1001_0000));
+    let gp = Options { address_model: AddressModel { gp: Some(0x1001_8000), ..AddressModel::MARS }, ..Options::default() };
+    assert_eq!(ok(src, &gp).text, vec![0x8F88_8000, 0x8389_8001]);
+}
+
+#[test]
+fn moves_the_bases() {
+    let src = "main: nop\nfoo: j foo\n.data\n.byte 1\nx: .word x\n";
+    let byte = ok(src, &Options { address_model: AddressModel::BYTE, ..Options::default() });
+    assert_eq!((byte.text[1], byte.data[1]), (0x0800_0001, 4));
+    // In words with word addressing
+    let opts = Options { address_model: AddressModel { text_base: 0x10, data_base: 0x20, ..AddressModel::WORD }, ..Options::default() };
+    let word = ok(src, &opts);
+    assert_eq!((word.text[1], word.data[1]), (0x0800_0011, 0x21));
+    assert_eq!(word.symbols["x"].offset, 4);
+}
@@ -0,0 +1,16 @@
+// Helpers shared by the test files, each of which only uses some of them
+#![allow(dead_code)]
+
+use quamgears::{assemble, AssembleError, ErrorKind, Options, Program};
+
+pub fn ok(src : &str, opts : &Options) -> Program {
+    assemble(src, opts).unwrap_or_else(|errors| panic!("{:?}", errors))
+}
+
+pub fn first_error(src : &str, opts : &Options) -> AssembleError {
+    assemble(src, opts).unwrap_err().remove(0)
+}
+
+pub fn error_kinds(src : &str, opts : &Options) -> Vec<ErrorKind> {
+    assemble(src, opts).unwrap_err().into_iter().map(|e| e.kind).collect()
+}
@@ -0,0 +1,38 @@
+// .if, .ifdef, .ifndef, .elseif, .else and .endif
+
+mod common;
+
+use common::{error_kinds, ok};
+use quamgears::{ErrorKind, Options};
+
+fn defined(name : &str, value : i64) -> Options {
+    Options { defines: vec![(String::from(name), value)], ..Options::default() }
+}
+
+#[test]
+fn assembles_conditionally() {
+    let src = "main:\n.ifdef BOARD\n addi $t0, $zero, 1\n.else\n addi $t0, $zero, 2\n.endif\n";
+    assert_eq!(ok(src, &Options::default()).text, vec![0x2008_0002]);
+    assert_eq!(ok(src, &defined("BOARD", 1)).text, vec![0x2008_0001]);
+}
+
+#[test]
+fn takes_the_first_branch_that_holds() {
+    let src = "main:\n.if MODE == 1\n addi $t0, $zero, 1\n.elseif MODE >= 2\n addi $t0, $zero, 2\n.elseif MODE >= 3\n addi $t0, $zero, 3\n.else\n addi $t0, $zero, 4\n.endif\n";
+    assert_eq!(ok(src, &defined("MODE", 1)).text, vec![0x2008_0001]);
+    assert_eq!(ok(src, &defined("MODE", 3)).text, vec![0x2008_0002]);
+    assert_eq!(ok(src, &defined("MODE", 0)).text, vec![0x2008_0004]);
+    // Inside a dropped branch even the nested .if is dropped
+    let nested = "main:\n.if 0\n.if 1\n nop\n.endif\n.elseif 1\n addi $t0, $zero, 5\n.endif\n";
+    assert_eq!(ok(nested, &Options::default()).text, vec![0x2008_0005]);
+}
+
+#[test]
+fn rejects_unbalanced_conditionals() {
+    assert_eq!(error_kinds("main: nop\n.if 1\n nop\n", &Options::default()), vec![ErrorKind::UnterminatedIf(String::from(".if"))]);
+    assert_eq!(error_kinds("main: nop\n.endif\n", &Options::default()), vec![ErrorKind::StrayConditional(String::from(".endif"))]);
+    assert_eq!(error_kinds("main: nop\n.elseif 1\n", &Options::default()), vec![ErrorKind::StrayConditional(String::from(".elseif"))]);
+    assert_eq!(error_kinds("main:\n.if 0\n.else\n.elseif 1\n.endif\n", &Options::default()),
+        vec![ErrorKind::ElseAfterElse(String::from(".elseif"))]);
+    assert_eq!(error_kinds("main:\n.if x\n.endif\nx: nop\n", &Options::default()), vec![ErrorKind::NotKnownYet(String::from("x"))]);
+}
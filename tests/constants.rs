@@ -0,0 +1,32 @@
+// .eqv, .equ and .set constants, and the names they can't take
+
+mod common;
+
+use common::{error_kinds, ok};
+use quamgears::{ErrorKind, Options};
+
+#[test]
+fn uses_constants_anywhere_a_number_goes() {
+    let program = ok(".eqv N, 4\n.equ SIZE N * 2\nmain: addi $t0, $zero, SIZE\n.data\n.word N, SIZE\n", &Options::default());
+    assert_eq!(program.text, vec![0x2008_0008]);
+    assert_eq!(program.data, vec![4, 8]);
+}
+
+#[test]
+fn only_redefines_set_constants() {
+    let program = ok(".set N, 1\nmain: addi $t0, $zero, N\n.set N, 2\n addi $t0, $zero, N\n", &Options::default());
+    assert_eq!(program.text, vec![0x2008_0001, 0x2008_0002]);
+    assert_eq!(error_kinds(".equ N, 1\nmain: nop\n.equ N, 2\n", &Options::default()),
+        vec![ErrorKind::ConstantRedefined(String::from("N"), 1)]);
+    assert_eq!(error_kinds(".eqv N, 1\nmain: nop\n.set N, 2\n", &Options::default()),
+        vec![ErrorKind::ConstantRedefined(String::from("N"), 1)]);
+}
+
+#[test]
+fn rejects_a_constant_named_like_a_label() {
+    let e = common::first_error("main: nop\n\nFOO: nop\n.eqv FOO, 3\n", &Options::default());
+    assert_eq!(e.kind, ErrorKind::ConstantIsLabel(String::from("FOO"), 3));
+    assert_eq!(e.span.map(|span| span.line), Some(4));
+    let defines = Options { defines: vec![(String::from("main"), 1)], ..Options::default() };
+    assert_eq!(error_kinds("main: nop\n", &defines), vec![ErrorKind::ConstantIsLabel(String::from("main"), 1)]);
+}
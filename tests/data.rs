@@ -0,0 +1,33 @@
+// How .word, .half, .byte, .space and .align are laid out and packed into the data words
+
+mod common;
+
+use common::{first_error, ok};
+use quamgears::{Endian, ErrorKind, Options};
+
+#[test]
+fn packs_bytes_and_halves_into_words() {
+    let src = "main: lb $t0, 3($t1)\n sh $t0, 2($t1)\n lhu $t0, 2($t1)\n.data\n.byte 1, 2, 3\nh: .half 0x1234\nw: .word 5\n";
+    let program = ok(src, &Options::default());
+    assert_eq!(program.text, vec![0x8128_0003, 0xA528_0002, 0x9528_0002]);
+    // The .half is aligned to 2 and the .word to 4
+    assert_eq!(program.data, vec![0x0102_0300, 0x1234_0000, 5]);
+    assert_eq!((program.symbols["h"].offset, program.symbols["w"].offset), (4, 8));
+    let little = ok(src, &Options { endian: Endian::Little, ..Options::default() });
+    assert_eq!(little.data, vec![0x0003_0201, 0x0000_1234, 5]);
+}
+
+#[test]
+fn checks_the_size_of_bytes_and_halves() {
+    assert_eq!(ok("main:\n.data\n.byte -128, 255\n.half -1\n", &Options::default()).data, vec![0x80FF_FFFF]);
+    assert_eq!(first_error("main:\n.data\n.byte 256\n", &Options::default()).kind, ErrorKind::OutOfRange(256, 0, 255));
+    assert_eq!(first_error("main:\n.data\n.half 0x10000\n", &Options::default()).kind, ErrorKind::OutOfRange(0x10000, 0, 0xFFFF));
+}
+
+#[test]
+fn rejects_data_bigger_than_the_memory() {
+    for src in ["main:\n.data\n.space 0xFFFFFFF0\n.word 1, 2, 3, 4, 5\n", "main:\n.data\n.space 0x7FFFFFFF\n", "main:\n.data\n.space 1024\n.kdata\n.word 1\n"] {
+        assert_eq!(first_error(src, &Options::default()).kind, ErrorKind::TooMuchData(256));
+    }
+    assert_eq!(ok("main:\n.data\n.space 1020\n.word 1\n", &Options::default()).data[255], 1);
+}
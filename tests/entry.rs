@@ -0,0 +1,31 @@
+// Where the program starts: main, the label given with --entry, or word 0, with a j there when needed
+
+mod common;
+
+use common::{error_kinds, ok};
+use quamgears::{ErrorKind, Options};
+
+fn entry(label : &str) -> Options {
+    Options { entry: Some(String::from(label)), ..Options::default() }
+}
+
+#[test]
+fn starts_at_word_0_without_main() {
+    let program = ok("start: nop\nloop: j loop\n", &Options::default());
+    assert_eq!(program.text, vec![0, 0x0800_0001]);
+}
+
+#[test]
+fn jumps_to_main_when_it_isnt_first() {
+    let program = ok("foo: nop\nmain: j foo\n", &Options::default());
+    assert_eq!(program.text, vec![0x0800_0002, 0, 0x0800_0001]);
+    assert_eq!((program.symbols["foo"].offset, program.symbols["main"].offset), (1, 2));
+    assert_eq!(ok("main: nop\nfoo: j main\n", &Options::default()).text, vec![0, 0x0800_0000]);
+}
+
+#[test]
+fn starts_at_the_entry_given() {
+    assert_eq!(ok("main: nop\nstart: j main\n", &entry("start")).text, vec![0x0800_0002, 0, 0x0800_0001]);
+    assert_eq!(error_kinds("main: nop\n", &entry("start")), vec![ErrorKind::MissingEntry(String::from("start"))]);
+    assert_eq!(error_kinds("main: nop\n.data\nstart: .word 1\n", &entry("start")), vec![ErrorKind::EntryNotCode(String::from("start"))]);
+}
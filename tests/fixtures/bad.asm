@@ -0,0 +1,2 @@
+main:
+    .include "bad.inc"
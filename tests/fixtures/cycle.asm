@@ -0,0 +1,2 @@
+main: nop
+    .include "cycle.inc"
@@ -0,0 +1,6 @@
+.include "macros.inc"
+main:
+    push($t0)
+    push($t1)
+.data
+value: .word 7
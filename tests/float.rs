@@ -0,0 +1,22 @@
+// Coprocessor 1: the float instructions and the IEEE 754 words of .float and .double
+
+mod common;
+
+use common::{first_error, ok};
+use quamgears::{Endian, ErrorKind, Options};
+
+#[test]
+fn encodes_float_instructions() {
+    let src = "main: c.lt.s $f2, $f4\n add.d $f0, $f2, $f4\n lwc1 $f2, 4($t0)\n mtc1 $t0, $f2\n cvt.s.w $f1, $f1\n";
+    assert_eq!(ok(src, &Options::default()).text, vec![0x4604_103C, 0x4624_1000, 0xC502_0004, 0x4488_1000, 0x4680_0860]);
+    assert_eq!(first_error("main: add.s $f0, $t0, $f2\n", &Options::default()).kind, ErrorKind::NotFloatRegister(String::from("t0")));
+}
+
+#[test]
+fn writes_ieee_words() {
+    let src = "main:\n.data\n.float 1.5, -0.25, 3\n.double 1.0\n";
+    assert_eq!(ok(src, &Options::default()).data, vec![0x3FC0_0000, 0xBE80_0000, 0x4040_0000, 0, 0x3FF0_0000, 0]);
+    // Little endian puts the low half of a double first
+    let little = Options { endian: Endian::Little, ..Options::default() };
+    assert_eq!(ok(src, &little).data, vec![0x3FC0_0000, 0xBE80_0000, 0x4040_0000, 0, 0, 0x3FF0_0000]);
+}
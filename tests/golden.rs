@@ -0,0 +1,19 @@
+// The example programs, checked word for word against the .mif files the first version of this assembler gave
+
+use quamgears::{assemble, mif, Options};
+
+fn render(src : &str) -> String {
+    let opts = Options::default();
+    let program = assemble(src, &opts).unwrap_or_else(|errors| panic!("{:?}", errors));
+    mif::program_mif(&program.text, opts.depth) + &mif::data_mif(&program.data, opts.depth)
+}
+
+#[test]
+fn test_program() {
+    assert_eq!(render(include_str!("../test_program.asm")), include_str!("golden/test_program.mif"));
+}
+
+#[test]
+fn test_all() {
+    assert_eq!(render(include_str!("../test_all.asm")), include_str!("golden/test_all.mif"));
+}
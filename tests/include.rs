@@ -0,0 +1,49 @@
+// .include: finding files, the file named in errors, cycles and the --depfile the binary writes
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use quamgears::{assemble_file, ErrorKind, Options};
+
+#[test]
+fn names_the_file_of_each_error() {
+    let errors = assemble_file(Path::new("tests/fixtures/bad.asm"), &Options::default()).unwrap_err();
+    assert_eq!(errors[0].kind, ErrorKind::OutOfRange(99999, -32768, 32767));
+    assert_eq!(errors[0].context.file.as_deref(), Some("tests/fixtures/bad.inc"));
+    let errors = assemble_file(Path::new("tests/fixtures/main.asm"), &Options { entry: Some(String::from("start")), ..Options::default() }).unwrap_err();
+    assert_eq!(errors[0].kind, ErrorKind::MissingEntry(String::from("start")));
+}
+
+#[test]
+fn finds_files_in_include_paths() {
+    let src = "    .include \"macros.inc\"\nmain: push($t0)\n";
+    assert_eq!(quamgears::assemble(src, &Options::default()).unwrap_err()[0].kind, ErrorKind::IncludeNotFound(String::from("macros.inc")));
+    let opts = Options { include_paths: vec![PathBuf::from("tests/fixtures")], ..Options::default() };
+    let program = quamgears::assemble(src, &opts).unwrap();
+    assert_eq!(program.text, vec![0x23BD_FFFC, 0xAFA8_0000]);
+    assert_eq!(program.includes, vec![PathBuf::from("tests/fixtures/macros.inc")]);
+}
+
+#[test]
+fn stops_files_that_include_each_other() {
+    let errors = assemble_file(Path::new("tests/fixtures/cycle.asm"), &Options::default()).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].kind, ErrorKind::IncludeCycle(String::from("cycle.asm")));
+    assert_eq!(errors[0].context.file.as_deref(), Some("tests/fixtures/cycle.inc"));
+}
+
+#[test]
+fn writes_a_depfile() {
+    let dir : PathBuf = std::env::temp_dir().join(format!("quamgears-depfile-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let depfile : PathBuf = dir.join("main.d");
+    let run = |extra : &[&str]| {
+        let status = Command::new(env!("CARGO_BIN_EXE_quamgears")).arg("tests/fixtures/main.asm").arg("--depfile").arg(&depfile)
+            .args(extra).stdout(std::process::Stdio::null()).status().unwrap();
+        assert!(status.success());
+        std::fs::read_to_string(&depfile).unwrap()
+    };
+    assert_eq!(run(&[]), "tests/fixtures/main.mif: tests/fixtures/main.asm tests/fixtures/macros.inc\n\ntests/fixtures/macros.inc:\n");
+    assert_eq!(run(&["--depfile-target", "out/my $file.mif"]),
+        "out/my\\ $$file.mif: tests/fixtures/main.asm tests/fixtures/macros.inc\n\ntests/fixtures/macros.inc:\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
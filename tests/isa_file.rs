@@ -0,0 +1,24 @@
+// Instruction set files given with --isa, which add instructions or replace pseudo-instructions
+
+mod common;
+
+use std::path::Path;
+use common::ok;
+use quamgears::{isa, ErrorKind, Options};
+
+#[test]
+fn assembles_instructions_from_a_file() {
+    let opts = Options { instructions: isa::load(Path::new("tests/fixtures/extra.isa")).unwrap(), ..Options::default() };
+    assert_eq!(ok("main: swap $t0, $t1, $t2\n push $t0\n halt\n", &opts).text, vec![0x012A_403F, 0xF51D_0000, 0xFC00_0000]);
+
+    let errors = isa::load(Path::new("tests/fixtures/bad.isa")).unwrap_err();
+    assert_eq!(errors[0].kind, ErrorKind::BadIsaValue(String::from("0x40"), String::from("opcode")));
+    assert_eq!(errors[0].context.file.as_deref(), Some("tests/fixtures/bad.isa"));
+}
+
+#[test]
+fn lets_isa_instructions_replace_pseudo_instructions() {
+    let opts = Options { instructions: isa::parse("move format=R opcode=0 funct=0x3E operands=rd,rs\n").unwrap(), ..Options::default() };
+    assert_eq!(ok("main: move $t0, $t1\n", &opts).text, vec![0x0120_403E]);
+    assert_eq!(ok("main: move $t0, $t1\n", &Options::default()).text, vec![0x0009_4021]);
+}
@@ -0,0 +1,43 @@
+// The exception handler: .ktext at the exception vector, .kdata after the .data, and the coprocessor 0 instructions
+
+mod common;
+
+use common::{first_error, ok};
+use quamgears::{AddressModel, ErrorKind, Options};
+
+#[test]
+fn puts_ktext_at_the_exception_vector() {
+    let program = ok("main: j main\n.ktext\nhandler: mfc0 $k0, $13\n eret\n", &Options::default());
+    assert_eq!(program.text.len(), 0x62);
+    assert_eq!(program.text[0x60..], [0x401A_6800, 0x4200_0018]);
+    assert_eq!(program.symbols["handler"].offset, 0x60);
+    let opts = Options { exception_vector: 4, ..Options::default() };
+    assert_eq!(ok("main: nop\n.ktext\n syscall\n", &opts).text, vec![0, 0, 0, 0, 0x0000_000C]);
+    assert_eq!(first_error("main: nop\n nop\n.ktext\n eret\n", &Options { exception_vector: 1, ..Options::default() }).kind,
+        ErrorKind::KernelOverlap(1));
+}
+
+#[test]
+fn puts_kdata_after_the_data() {
+    let src = "main: lw $t0, k\n.kdata\nk: .word 3\n.data\nx: .word 1\n";
+    let program = ok(src, &Options::default());
+    // At the next multiple of 8 bytes
+    assert_eq!(program.data, vec![1, 0, 3]);
+    assert_eq!(program.text, vec![0x8C08_0002]);
+}
+
+#[test]
+fn keeps_kdata_doubles_aligned() {
+    let src = "main: ldc1 $f0, d\n lw $t0, k\n.data\n.word 1\n.kdata\nd: .double 1.0\nk: .word 3\n";
+    let program = ok(src, &Options { address_model: AddressModel::BYTE, ..Options::default() });
+    assert_eq!(program.text, vec![0xD400_0008, 0x8C08_0010]);
+    assert_eq!(program.data, vec![1, 0, 0x3FF0_0000, 0, 3]);
+}
+
+#[test]
+fn rejects_an_exception_vector_outside_the_memory() {
+    let opts = Options { exception_vector: u32::MAX, ..Options::default() };
+    assert_eq!(first_error("main: nop\n.ktext\n eret\n", &opts).kind, ErrorKind::VectorOutOfMemory(u32::MAX, 256));
+    // Only the .ktext code goes there
+    assert_eq!(ok("main: nop\n", &opts).text, vec![0]);
+}
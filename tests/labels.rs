@@ -0,0 +1,34 @@
+// Labels on their own lines or before a statement, and statements continued after a trailing comma
+
+mod common;
+
+use common::{error_kinds, ok};
+use quamgears::{ErrorKind, Options};
+
+#[test]
+fn labels_point_at_the_next_statement() {
+    let program = ok("main:\n\nloop:\nagain: addi $t0, $t0, 1\n j loop\nend: j again\n", &Options::default());
+    assert_eq!(program.symbols["loop"].offset, 0);
+    assert_eq!(program.symbols["again"].offset, 0);
+    assert_eq!(program.symbols["end"].offset, 2);
+    assert_eq!(program.text[1..], [0x0800_0000, 0x0800_0000]);
+}
+
+#[test]
+fn data_labels_skip_the_padding_before_their_data() {
+    let program = ok("main: lw $t0, x\n.data\n.byte 1\nx:\n\n.word 5\n", &Options::default());
+    assert_eq!(program.symbols["x"].offset, 4);
+    assert_eq!(program.text[0], 0x8C08_0001);
+}
+
+#[test]
+fn a_trailing_comma_continues_the_line() {
+    let program = ok("main: add $t0,\n    $t1,\n\n    $t2\n.data\n.word 1,\n 2\n", &Options::default());
+    assert_eq!(program.text, vec![0x012A_4020]);
+    assert_eq!(program.data, vec![1, 2]);
+}
+
+#[test]
+fn rejects_a_label_defined_twice() {
+    assert_eq!(error_kinds("main: nop\nmain:\n nop\n", &Options::default()), vec![ErrorKind::DuplicateLabel(String::from("main"))]);
+}
@@ -0,0 +1,44 @@
+// Macros: parameters, local labels, and the calls an error came through
+
+mod common;
+
+use std::path::{Path, PathBuf};
+use common::{first_error, ok};
+use quamgears::{assemble_file, ErrorKind, Options};
+
+#[test]
+fn expands_macros_from_included_files() {
+    let program = assemble_file(Path::new("tests/fixtures/main.asm"), &Options::default()).unwrap();
+    assert_eq!(program.text, vec![0x23BD_FFFC, 0xAFA8_0000, 0x23BD_FFFC, 0xAFA9_0000]);
+    assert_eq!(program.data[0], 7);
+    assert_eq!(program.includes, vec![PathBuf::from("tests/fixtures/macros.inc")]);
+}
+
+#[test]
+fn renames_macro_labels_in_each_expansion() {
+    let src = ".macro wait(%n)\n addi $t0, $zero, %n\nagain: addi $t0, $t0, -1\n bne $t0, $zero, again\n.end_macro\nmain: wait(3)\n wait(5)\n";
+    let program = ok(src, &Options::default());
+    assert_eq!(program.text.len(), 6);
+    assert_eq!(program.text[2], 0x1408_FFFE);
+    assert_eq!(program.text[5], 0x1408_FFFE);
+}
+
+#[test]
+fn stops_a_macro_that_calls_itself() {
+    let e = first_error(".macro forever\n forever\n.end_macro\nmain: forever\n", &Options::default());
+    assert_eq!(e.kind, ErrorKind::MacroTooDeep(String::from("forever"), 64));
+    // The 63 calls from inside the body are the same line, so they're shown once
+    let lines : Vec<u32> = e.context.calls.iter().map(|call| call.span.line).collect();
+    assert_eq!(lines, vec![2, 4]);
+}
+
+#[test]
+fn shows_the_calls_an_error_came_through() {
+    let src = ".macro inner\n addi $t0, $zero, 99999\n.end_macro\n.macro outer\n nop\n inner\n.end_macro\nmain: outer\n";
+    let e = first_error(src, &Options::default());
+    assert_eq!(e.kind, ErrorKind::OutOfRange(99999, -32768, 32767));
+    assert_eq!(e.span.map(|span| span.line), Some(2));
+    let calls : Vec<(&str, u32)> = e.context.calls.iter().map(|call| (call.name.as_str(), call.span.line)).collect();
+    assert_eq!(calls, vec![("inner", 6), ("outer", 8)]);
+    assert_eq!(e.context.calls[1].snippet, "main: outer");
+}
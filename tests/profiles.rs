@@ -0,0 +1,41 @@
+// Profiles, which limit a program to the instructions a processor has
+
+mod common;
+
+use common::{first_error, ok};
+use quamgears::isa::{self, Profile};
+use quamgears::{ErrorKind, Options};
+
+#[test]
+fn builds_profiles() {
+    let small = Profile::named("single-cycle-15").unwrap();
+    assert_eq!(small.mnemonics.len(), 15);
+    assert!(small.allows("LW") && !small.allows("addu"));
+    let mips_i = Profile::named("mips-i").unwrap();
+    assert!(mips_i.allows("mult") && !mips_i.allows("mul"));
+    assert!(Profile::named("mips32r2").unwrap().allows("eret"));
+    assert_eq!(Profile::named("mips64"), None);
+
+    let errors = isa::parse_profile("hw", "lw sw\nli frob\n", &[]).unwrap_err();
+    assert_eq!(errors[0].kind, ErrorKind::PseudoInProfile(String::from("li")));
+    assert_eq!(errors[1].kind, ErrorKind::UnknownInstruction(String::from("frob")));
+}
+
+#[test]
+fn checks_profiles_through_pseudo_instructions() {
+    let opts = Options { profile: Profile::named("single-cycle-15"), ..Options::default() };
+    let e = first_error("main: li $t0, 5\n", &opts);
+    assert_eq!(e.kind, ErrorKind::PseudoNotInProfile(String::from("li"), String::from("addiu"), String::from("single-cycle-15")));
+    assert_eq!(first_error("main: addu $t0, $t0, $t0\n", &opts).kind,
+        ErrorKind::NotInProfile(String::from("addu"), String::from("single-cycle-15")));
+}
+
+#[test]
+fn checks_isa_instructions_against_the_profile() {
+    let instructions = isa::parse("halt format=J opcode=0x3F\n").unwrap();
+    let profile = |text : &str| Some(isa::parse_profile("hw", text, &instructions).unwrap());
+    let without = Options { instructions: instructions.clone(), profile: profile("lw sw\n"), ..Options::default() };
+    assert_eq!(first_error("main: halt\n", &without).kind, ErrorKind::NotInProfile(String::from("halt"), String::from("hw")));
+    let with = Options { instructions: instructions.clone(), profile: profile("lw sw halt\n"), ..Options::default() };
+    assert_eq!(ok("main: halt\n", &with).text, vec![0xFC00_0000]);
+}
@@ -0,0 +1,21 @@
+// Pseudo-instructions: their expansions, and the words they take before the labels after them are placed
+
+mod common;
+
+use common::ok;
+use quamgears::Options;
+
+#[test]
+fn sizes_pseudo_instructions_before_the_labels_after_them() {
+    let program = ok("main: li $t0, 5\n li $t1, 0x12345\n la $t2, value\nend: j end\n.data\nvalue: .word 1\n", &Options::default());
+    assert_eq!(program.text, vec![0x2408_0005, 0x3C01_0001, 0x3429_2345, 0x3C01_0000, 0x342A_0000, 0x0800_0005]);
+    assert_eq!(program.symbols["end"].offset, 5);
+}
+
+#[test]
+fn branches_from_the_last_word_of_the_expansion() {
+    let program = ok("main: blt $t0, $t1, end\n nop\nend: b main\n", &Options::default());
+    // slt $at, $t0, $t1, then bne $at, $zero over the nop, then a beq back to word 0
+    assert_eq!(program.text, vec![0x0109_082A, 0x1401_0001, 0, 0x1000_FFFC]);
+    assert_eq!(program.symbols["end"].offset, 3);
+}
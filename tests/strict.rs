@@ -0,0 +1,31 @@
+// --strict only takes lowercase mnemonics and $name registers
+
+mod common;
+
+use common::{error_kinds, ok};
+use quamgears::{ErrorKind, Options};
+
+fn not_canonical(written : &str, canonical : &str) -> ErrorKind {
+    ErrorKind::NotCanonical(String::from(written), String::from(canonical))
+}
+
+#[test]
+fn takes_anything_by_default() {
+    let program = ok("main: ADD $T0, $9, t2\n add.s $F0, $f1, $f2\n", &Options::default());
+    assert_eq!(program.text[0], 0x012A_4020);
+}
+
+#[test]
+fn only_takes_canonical_names_when_strict() {
+    let strict = Options { strict: true, ..Options::default() };
+    ok("main: add $t0, $t1, $t2\n add.s $f0, $f1, $f2\n", &strict);
+    assert_eq!(error_kinds("main: ADD $T0, $9, t2\n add.s $F0, $f01, $f2\n LI $t0, 1\n", &strict), vec![
+        not_canonical("ADD", "add"),
+        not_canonical("$F0", "$f0"),
+        not_canonical("LI", "li"),
+    ]);
+    assert_eq!(error_kinds("main: add $T0, $9, t2\n", &strict), vec![not_canonical("$T0", "$t0")]);
+    assert_eq!(error_kinds("main: add $t0, $9, $t2\n", &strict), vec![not_canonical("$9", "$t1")]);
+    assert_eq!(error_kinds("main: add $t0, $t1, t2\n", &strict), vec![not_canonical("t2", "$t2")]);
+    assert_eq!(error_kinds("main: add $fp, $s8, $t2\n", &strict), vec![not_canonical("$s8", "$fp")]);
+}